@@ -0,0 +1,33 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use disbot_v2::braille::{self, BrailleConfig};
+use image::imageops::FilterType;
+use std::hint::black_box;
+
+fn image_to_patterns(c: &mut Criterion) {
+    let sample = image::open("./sample_face.png").unwrap();
+    let config = BrailleConfig::default();
+
+    let mut group = c.benchmark_group("image_to_patterns");
+    // width in braille character
+    for width in [60, 250, 1000] {
+        let (w, h) = (sample.width(), sample.height());
+        let image = sample
+            .resize_exact(2 * width, h * 2 * width / w, FilterType::Triangle)
+            .to_luma8();
+
+        group.bench_with_input(BenchmarkId::new("generic", width), &image, |b, image| {
+            b.iter(|| {
+                braille::image_to_patterns(black_box(image), &config)
+                    .map(|row| row.collect::<String>())
+                    .collect::<Vec<_>>()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("gray", width), &image, |b, image| {
+            b.iter(|| braille::gray_to_patterns(black_box(image), &config))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, image_to_patterns);
+criterion_main!(benches);
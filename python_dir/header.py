@@ -1,40 +1,41 @@
-# Blacklist builtins
-del __builtins__.__dict__['open']
+# Blacklist builtins, `banned_builtins` and `allowed_modules` are set by the
+# sandbox policy prelude
+[__builtins__.__dict__.pop(name, None) for name in banned_builtins]
 
 import importlib
+import sys
 
+# a prelude before this one can trust the modules it loaded to import whatever
+# they need themselves
+trusted_modules = globals().get('trusted_modules', set())
+
+
+def secure_importer_factory(importlib, getframe, modules, allowed_modules, trusted_modules):
+    # the `globals` argument is given by the caller and may be made up, so the
+    # caller is the frame really importing, running in a trusted module's own
+    # namespace (not `exec` with a copy of it)
+    def trusted_caller():
+        caller = getframe(2).f_globals
+        name = str(caller.get('__name__', ''))
+        return (
+            name.split('.')[0] in trusted_modules
+            and getattr(modules.get(name), '__dict__', None) is caller
+        )
 
-def secure_importer_factory(importlib):
     def secure_importer(name, globals=None, locals=None, fromlist=(), level=0):
-        whitelist = {
-        "numpy",
-        "scipy",
-        "math",
-        "string",
-        "re",
-        "struct",
-        "datetime",
-        "collections",
-        "enum",
-        "fractions",
-        "itertools",
-        "functools",
-        "random",
-        "glob",
-        "hashlib",
-        "time",
-        "queue",
-    }
-        if name.split('.')[0] in whitelist:
+        if name.split('.')[0] in allowed_modules or trusted_caller():
             return importlib.__import__(name, globals, locals, fromlist, level)
         else:
             raise ImportError(f"module `{name}` is not whitelist")
 
     return secure_importer
 
-__builtins__.__dict__['__import__'] = secure_importer_factory(importlib)
+__builtins__.__dict__['__import__'] = secure_importer_factory(
+    importlib, sys._getframe, sys.modules, allowed_modules, trusted_modules
+)
 importlib = None  # prevent using importlib in python code
-del secure_importer_factory
+sys = None
+del secure_importer_factory, allowed_modules, banned_builtins, trusted_modules
 
 # Default import
 try:
@@ -0,0 +1,94 @@
+# Plot mode, after the policy prelude and before header.py: matplotlib (Agg)
+# and PIL are allowed, images shown and figures still open at the end are saved
+# as PNG into `out/`, which is then sent on stdout after a marker line
+#
+# The first line of stdin is the marker, the most images to send and the
+# largest size of one in bytes.
+import atexit
+import io
+import os
+import sys
+
+try:
+    import matplotlib
+    matplotlib.use('Agg')
+    import matplotlib.pyplot
+except ImportError:
+    pass
+try:
+    import PIL.Image
+except ImportError:
+    pass
+
+
+# what it use is kept here, out of reach of the code
+def plot_setup(open, io, os, sys, pyplot, Image):
+    marker, max_images, max_size = sys.stdin.buffer.readline().split()
+    max_images, max_size = int(max_images), int(max_size)
+    stdout = sys.stdout
+    os.mkdir('out')
+    count = 0
+
+    def save(buffer):
+        nonlocal count
+        count += 1
+        with open(f'out/{count}.png', 'wb') as file:
+            file.write(buffer.getvalue())
+
+    def show_figures(*args, **kwargs):
+        for number in pyplot.get_fignums():
+            buffer = io.BytesIO()
+            pyplot.figure(number).savefig(buffer, format='png')
+            save(buffer)
+        pyplot.close('all')
+
+    def show_image(image, *args, **kwargs):
+        buffer = io.BytesIO()
+        image.save(buffer, format='PNG')
+        save(buffer)
+
+    def send_images():
+        if pyplot:
+            show_figures()
+        names = sorted(
+            (name for name in os.listdir('out') if name.endswith('.png')),
+            key=lambda name: (len(name), name),
+        )
+        images = []
+        for name in names:
+            with open(f'out/{name}', 'rb') as file:
+                image = file.read()
+            if len(image) > max_size:
+                print(f'[{name} is over {max_size >> 20} MiB, skipped]', file=sys.stderr)
+            elif len(images) == max_images:
+                print(f'[only {max_images} images are sent]', file=sys.stderr)
+                break
+            else:
+                images.append((name, image))
+
+        sys.stdout.flush()
+        stdout.flush()
+        stdout.buffer.write(b'\n' + marker + b'\n')
+        for name, image in images:
+            stdout.buffer.write(f'{len(image)} {name}\n'.encode() + image)
+        stdout.buffer.flush()
+
+    if pyplot:
+        pyplot.show = show_figures
+    if Image:
+        Image.Image.show = show_image
+    atexit.register(send_images)
+
+
+plot_setup(
+    open,
+    io,
+    os,
+    sys,
+    sys.modules.get('matplotlib.pyplot'),
+    sys.modules.get('PIL.Image'),
+)
+allowed_modules = allowed_modules | {'matplotlib', 'PIL'}
+# what is loaded now may import what it needs later on
+trusted_modules = {name.split('.')[0] for name in sys.modules} - {'__main__'}
+del atexit, io, os, sys, plot_setup
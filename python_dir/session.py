@@ -0,0 +1,53 @@
+# Session: run snippets one after another in the same namespace
+#
+# The first line of stdin is a marker, then each snippet is its code and stdin
+# sizes in a line followed by both. Its output on stdout and stderr each end with
+# a line of the marker and its status (0 or 1).
+import ast
+import io
+import linecache
+import sys
+import traceback
+
+marker = sys.stdin.buffer.readline().strip().decode()
+with open('header.py') as file:
+    header = file.read()
+namespace = {'__name__': '__main__', '__builtins__': __builtins__}
+exec(compile(header, 'header.py', 'exec'), namespace)
+del header
+
+requests = sys.stdin.buffer
+stdout, stderr = sys.stdout, sys.stderr
+count = 0
+while line := requests.readline():
+    code_size, input_size = map(int, line.split())
+    code = requests.read(code_size).decode()
+    sys.stdin = io.TextIOWrapper(io.BytesIO(requests.read(input_size)))
+    count += 1
+    name = f'<in {count}>'
+    linecache.cache[name] = (len(code), None, code.splitlines(True), name)
+
+    status = 0
+    try:
+        # print the value of a last expression, like the python shell
+        tree = ast.parse(code, name)
+        last = tree.body.pop() if tree.body and isinstance(tree.body[-1], ast.Expr) else None
+        exec(compile(tree, name, 'exec'), namespace)
+        if last is not None:
+            value = eval(compile(ast.Expression(last.value), name, 'eval'), namespace)
+            if value is not None:
+                print(repr(value))
+    except SystemExit:
+        raise
+    except SyntaxError as error:
+        traceback.print_exception(error.with_traceback(None))
+        status = 1
+    except BaseException as error:
+        # hide this file's frame
+        traceback.print_exception(error.with_traceback(error.__traceback__.tb_next))
+        status = 1
+
+    sys.stdout, sys.stderr = stdout, stderr
+    for file in (stdout, stderr):
+        file.write(f'\n{marker} {status}\n')
+        file.flush()
@@ -0,0 +1,24 @@
+# Warm worker: run header.py ahead of time, then one snippet sent on stdin
+# as its length in a line followed by the code, the rest of stdin is its own
+import linecache
+import sys
+import traceback
+
+with open('header.py') as file:
+    header = file.read()
+namespace = {'__name__': '__main__', '__builtins__': __builtins__}
+exec(compile(header, 'header.py', 'exec'), namespace)
+del header
+
+size = int(sys.stdin.buffer.readline())
+code = sys.stdin.buffer.read(size).decode()
+# so traceback can show the code lines
+linecache.cache['main.py'] = (len(code), None, code.splitlines(True), 'main.py')
+try:
+    exec(compile(code, 'main.py', 'exec'), namespace)
+except SystemExit:
+    raise
+except BaseException as error:
+    # hide this file's frame
+    traceback.print_exception(error.with_traceback(error.__traceback__.tb_next))
+    sys.exit(1)
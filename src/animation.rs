@@ -0,0 +1,247 @@
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{
+    AnimationDecoder, DynamicImage, Frames, ImageDecoder, ImageFormat, ImageResult, Limits,
+};
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+use crate::decode;
+
+// browsers play frame with (almost) zero delay at this speed, so do we
+const ZERO_DELAY_FALLBACK: Duration = Duration::from_millis(100);
+
+/// Whether `data` is an animated gif, png (apng) or webp
+pub fn is_animated(data: &[u8]) -> bool {
+    match image::guess_format(data) {
+        Ok(ImageFormat::Gif) => true,
+        Ok(ImageFormat::Png) => PngDecoder::new(Cursor::new(data))
+            .and_then(|decoder| decoder.is_apng())
+            .unwrap_or(false),
+        Ok(ImageFormat::WebP) => WebPDecoder::new(Cursor::new(data))
+            .map(|decoder| decoder.has_animation())
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn frames(data: &[u8], limits: Limits) -> ImageResult<Frames<'_>> {
+    let data = Cursor::new(data);
+    Ok(match image::guess_format(data.get_ref())? {
+        ImageFormat::Png => PngDecoder::with_limits(data, limits)?.apng()?.into_frames(),
+        ImageFormat::WebP => {
+            let mut decoder = WebPDecoder::new(data)?;
+            decoder.set_limits(limits)?;
+            decoder.into_frames()
+        }
+        _ => {
+            let mut decoder = GifDecoder::new(data)?;
+            decoder.set_limits(limits)?;
+            decoder.into_frames()
+        }
+    })
+}
+
+/// Decode frames of an animation and `map` them, each paired with the time it should be shown.
+///
+/// Frames are skipped so shown frames are at least `min_interval` apart,
+/// and decoding stop once the animation reach `max_duration`.
+/// Only one decoded frame is alive at a time, so memory stay within `limits`,
+/// and it fails with [`decode::Error::Timeout`] if a frame end past `deadline`.
+pub fn decode<T>(
+    data: &[u8],
+    min_interval: Duration,
+    max_duration: Duration,
+    limits: &decode::Limits,
+    deadline: Instant,
+    mut map: impl FnMut(DynamicImage) -> T,
+) -> Result<Vec<(Duration, T)>, decode::Error> {
+    let frames = frames(data, limits.image_limits()).map_err(|e| limits.error(e))?;
+    let frames = frames.map(|frame| {
+        // every frame, skipped ones are decoded too as later frames draw over them
+        if Instant::now() >= deadline {
+            return Err(decode::Error::Timeout {
+                timeout: limits.timeout,
+            });
+        }
+        let frame = frame.map_err(|e| limits.error(e))?;
+        Ok((Duration::from(frame.delay()), frame))
+    });
+    select_frames(frames, min_interval, max_duration, |frame| {
+        map(DynamicImage::ImageRgba8(frame.into_buffer()))
+    })
+}
+
+fn select_frames<F, T, E>(
+    frames: impl Iterator<Item = Result<(Duration, F), E>>,
+    min_interval: Duration,
+    max_duration: Duration,
+    mut map: impl FnMut(F) -> T,
+) -> Result<Vec<(Duration, T)>, E> {
+    let mut selected = Vec::new();
+    let (mut at, mut next_shown) = (Duration::ZERO, Duration::ZERO);
+
+    for frame in frames {
+        if at >= max_duration {
+            break;
+        }
+        let (delay, frame) = frame?;
+        if at >= next_shown {
+            selected.push((at, map(frame)));
+            next_shown = at + min_interval;
+        }
+        at += if delay <= Duration::from_millis(10) {
+            ZERO_DELAY_FALLBACK
+        } else {
+            delay
+        };
+    }
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, RgbaImage};
+    use std::convert::Infallible;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn limits() -> decode::Limits {
+        decode::Limits::default()
+    }
+
+    fn far() -> Instant {
+        Instant::now() + Duration::from_secs(60)
+    }
+
+    fn select(delays: &[u64], min_interval: u64, max_duration: u64) -> Vec<(u64, usize)> {
+        let frames = delays
+            .iter()
+            .enumerate()
+            .map(|(i, &d)| Ok::<_, Infallible>((ms(d), i)));
+        select_frames(frames, ms(min_interval), ms(max_duration), |i| i)
+            .unwrap()
+            .into_iter()
+            .map(|(at, i)| (at.as_millis() as u64, i))
+            .collect()
+    }
+
+    #[test]
+    fn keep_all_slow_frames() {
+        assert_eq!(
+            select(&[1000, 1000, 1000], 1000, 10_000),
+            [(0, 0), (1000, 1), (2000, 2)]
+        );
+    }
+
+    #[test]
+    fn skip_fast_frames() {
+        assert_eq!(select(&[400; 6], 1000, 10_000), [(0, 0), (1200, 3)]);
+    }
+
+    #[test]
+    fn stop_at_max_duration() {
+        assert_eq!(
+            select(&[1000; 100], 1000, 3000),
+            [(0, 0), (1000, 1), (2000, 2)]
+        );
+    }
+
+    #[test]
+    fn zero_delay_fallback() {
+        assert_eq!(
+            select(&[0; 25], 1000, 10_000),
+            [(0, 0), (1000, 10), (2000, 20)]
+        );
+    }
+
+    #[test]
+    fn decode_gif() {
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            for i in 0..4 {
+                let frame = Frame::from_parts(
+                    RgbaImage::from_pixel(4, 4, image::Rgba([i * 60, 0, 0, 255])),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(500, 1),
+                );
+                encoder.encode_frame(frame).unwrap();
+            }
+        }
+
+        assert!(is_animated(&gif));
+        let frames = decode(&gif, ms(1000), ms(10_000), &limits(), far(), |f| f).unwrap();
+        assert_eq!(
+            frames
+                .iter()
+                .map(|(at, _)| at.as_millis())
+                .collect::<Vec<_>>(),
+            [0, 1000]
+        );
+        assert_eq!(frames[0].1.width(), 4);
+    }
+
+    #[test]
+    fn frame_over_limits() {
+        let mut gif = Vec::new();
+        GifEncoder::new(&mut gif)
+            .encode_frame(Frame::new(RgbaImage::new(16, 16)))
+            .unwrap();
+
+        let limits = decode::Limits {
+            max_dimension: 8,
+            ..limits()
+        };
+        assert!(matches!(
+            decode(&gif, ms(1000), ms(10_000), &limits, far(), |f| f),
+            Err(decode::Error::Dimension { max_dimension: 8 })
+        ));
+    }
+
+    #[test]
+    fn stop_at_deadline() {
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            for _ in 0..100 {
+                let frame = Frame::from_parts(
+                    RgbaImage::new(4, 4),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(11, 1),
+                );
+                encoder.encode_frame(frame).unwrap();
+            }
+        }
+
+        let mut decoded = 0;
+        let result = decode(
+            &gif,
+            ms(1000),
+            ms(10_000),
+            &limits(),
+            Instant::now(),
+            |_| {
+                decoded += 1;
+            },
+        );
+        assert!(matches!(result, Err(decode::Error::Timeout { .. })));
+        assert_eq!(decoded, 0);
+    }
+
+    #[test]
+    fn still_image_is_not_animated() {
+        let mut png = Vec::new();
+        DynamicImage::new_luma8(4, 4)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        assert!(!is_animated(&png));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::braille::{self, BrailleConfig};
+use crate::choice::choice_enum;
+use image::{GrayImage, Rgb, RgbImage};
+use std::fmt::Write;
+
+choice_enum! {
+    /// Which part of a character get colored
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum ColorMode {
+        #[default]
+        None = "none",
+        /// Color only the glyph
+        Foreground = "foreground",
+        /// Color both the glyph and the cell behind it
+        Full = "full",
+    }
+}
+
+// (SGR code, approximate color) as rendered by discord in ```ansi code block
+const FOREGROUND: [(u8, [u8; 3]); 8] = [
+    (30, [0x4f, 0x54, 0x5c]), // gray
+    (31, [0xdc, 0x32, 0x2f]), // red
+    (32, [0x85, 0x99, 0x00]), // green
+    (33, [0xb5, 0x89, 0x00]), // yellow
+    (34, [0x26, 0x8b, 0xd2]), // blue
+    (35, [0xd3, 0x36, 0x82]), // pink
+    (36, [0x2a, 0xa1, 0x98]), // cyan
+    (37, [0xff, 0xff, 0xff]), // white
+];
+const BACKGROUND: [(u8, [u8; 3]); 8] = [
+    (40, [0x00, 0x2b, 0x36]), // firefly dark blue
+    (41, [0xcb, 0x4b, 0x16]), // orange
+    (42, [0x58, 0x6e, 0x75]), // marble blue
+    (43, [0x65, 0x7b, 0x83]), // greyish turquoise
+    (44, [0x83, 0x94, 0x96]), // gray
+    (45, [0x6c, 0x71, 0xc4]), // indigo
+    (46, [0x93, 0xa1, 0xa1]), // light gray
+    (47, [0xfd, 0xf6, 0xe3]), // white
+];
+
+/// SGR code of the palette color closest to `color`
+fn nearest(palette: &[(u8, [u8; 3])], color: Rgb<u8>) -> u8 {
+    let distance = |rgb: &[u8; 3]| {
+        rgb.iter()
+            .zip(color.0)
+            .map(|(&a, b)| (a as i32 - b as i32).pow(2))
+            .sum::<i32>()
+    };
+    palette
+        .iter()
+        .min_by_key(|(_, rgb)| distance(rgb))
+        .map(|&(code, _)| code)
+        .expect("palette not empty")
+}
+
+fn average(colors: impl Iterator<Item = Rgb<u8>>) -> Option<Rgb<u8>> {
+    let (mut sum, mut n) = ([0u32; 3], 0);
+    for color in colors {
+        sum.iter_mut()
+            .zip(color.0)
+            .for_each(|(s, c)| *s += c as u32);
+        n += 1;
+    }
+    (n > 0).then(|| Rgb(sum.map(|s| (s / n) as u8)))
+}
+
+/// Add ANSI color escape to each character of `rows`.
+///
+/// `rows` must be rendered from `luma` with cell of `cell_size`, `image` is the color source
+/// with the same dimension. Glyph is colored by the average of its dot pixels and background
+/// by the rest. Every row start with a full escape so rows can be split across messages.
+pub fn colorize(
+    rows: &[String],
+    image: &RgbImage,
+    luma: &GrayImage,
+    cell_size: (u32, u32),
+    config: &BrailleConfig,
+    mode: ColorMode,
+) -> Vec<String> {
+    if mode == ColorMode::None {
+        return rows.to_vec();
+    }
+
+    let (cw, ch) = cell_size;
+    rows.iter()
+        .enumerate()
+        .map(|(row, text)| {
+            let mut line = String::with_capacity(text.len() * 2);
+            let mut current = None;
+
+            for (col, c) in text.chars().enumerate() {
+                let (x0, y0) = (col as u32 * cw, row as u32 * ch);
+                // cell cut by the image edge only count pixel inside
+                let pixels = itertools::iproduct!(y0..y0 + ch, x0..x0 + cw);
+                let pixels = pixels.filter(|&(y, x)| x < image.width() && y < image.height());
+                let pixels = pixels.map(|(y, x)| {
+                    let is_dot = braille::is_dot(luma.get_pixel(x, y).0[0], config);
+                    (is_dot, *image.get_pixel(x, y))
+                });
+
+                let dot = average(pixels.clone().filter(|&(d, _)| d).map(|(_, c)| c));
+                let blank = average(pixels.clone().filter(|&(d, _)| !d).map(|(_, c)| c));
+                let all = average(pixels.map(|(_, c)| c)).expect("cell not empty");
+
+                let fg = nearest(&FOREGROUND, dot.unwrap_or(all));
+                let bg =
+                    (mode == ColorMode::Full).then(|| nearest(&BACKGROUND, blank.unwrap_or(all)));
+
+                if current != Some((fg, bg)) {
+                    match bg {
+                        Some(bg) => write!(line, "\u{1b}[{fg};{bg}m"),
+                        None => write!(line, "\u{1b}[{fg}m"),
+                    }
+                    .expect("write to string");
+                    current = Some((fg, bg));
+                }
+                line.push(c);
+            }
+            line
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn nearest_color() {
+        assert_eq!(nearest(&FOREGROUND, Rgb([250, 10, 10])), 31);
+        assert_eq!(nearest(&FOREGROUND, Rgb([255, 255, 255])), 37);
+        assert_eq!(nearest(&BACKGROUND, Rgb([0, 0, 0])), 40);
+    }
+
+    #[test]
+    fn escape_only_on_change() {
+        // left half red, right half blue, all pixel are dot
+        let image = RgbImage::from_fn(8, 4, |x, _| {
+            if x < 4 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([0, 0, 255])
+            }
+        });
+        let luma = GrayImage::from_pixel(8, 4, Luma([0]));
+        let rows = ["⣿⣿⣿⣿".to_owned()];
+        let config = BrailleConfig::default();
+
+        let colored = colorize(&rows, &image, &luma, (2, 4), &config, ColorMode::Foreground);
+        assert_eq!(colored, ["\u{1b}[31m⣿⣿\u{1b}[34m⣿⣿"]);
+
+        let colored = colorize(&rows, &image, &luma, (2, 4), &config, ColorMode::None);
+        assert_eq!(colored, rows);
+    }
+
+    #[test]
+    fn background_from_blank_pixel() {
+        // top row is a white dot, the rest is red blank
+        let image = RgbImage::from_fn(1, 2, |_, y| {
+            if y == 0 {
+                Rgb([255, 255, 255])
+            } else {
+                Rgb([255, 80, 20])
+            }
+        });
+        let luma = GrayImage::from_fn(1, 2, |_, y| Luma([if y == 0 { 0 } else { 255 }]));
+        let colored = colorize(
+            &["▀".to_owned()],
+            &image,
+            &luma,
+            (1, 2),
+            &BrailleConfig::default(),
+            ColorMode::Full,
+        );
+        assert_eq!(colored, ["\u{1b}[37;41m▀"]);
+    }
+
+    #[test]
+    fn every_row_start_with_escape() {
+        let image = RgbImage::from_pixel(2, 8, Rgb([0, 255, 0]));
+        let luma = GrayImage::from_pixel(2, 8, Luma([0]));
+        let rows = ["⣿".to_owned(), "⣿".to_owned()];
+        let colored = colorize(
+            &rows,
+            &image,
+            &luma,
+            (2, 4),
+            &BrailleConfig::default(),
+            ColorMode::Foreground,
+        );
+        assert!(colored.iter().all(|row| row.starts_with('\u{1b}')));
+    }
+}
@@ -0,0 +1,198 @@
+use color_eyre::eyre::Result;
+use fontdue::layout::{CoordinateSystem, Layout, TextStyle};
+use fontdue::{Font, FontSettings};
+use image::{GrayImage, Luma};
+use poise::serenity_prelude::CreateAttachment;
+use poise::{command, CreateReply};
+use std::sync::LazyLock;
+
+use disbot_v2::braille::{self, BrailleConfig};
+
+use crate::paginator::Paginator;
+use crate::{Context, DISCORD_MESSAGE_LIMIT};
+
+// font height in pixel, a braille row is 4 pixel tall
+const DEFAULT_FONT_SIZE: u8 = 24;
+const MIN_FONT_SIZE: u8 = 8;
+const MAX_FONT_SIZE: u8 = 96;
+const MAX_TEXT_LENGTH: usize = 200;
+const MAX_MESSAGES: usize = 3;
+
+static FONT: LazyLock<Font> = LazyLock::new(|| {
+    let data = include_bytes!("../fonts/DejaVuSans-Bold.ttf") as &[u8];
+    Font::from_bytes(data, FontSettings::default()).expect("bundled font to be valid")
+});
+
+/// Write text in big braille letters
+///
+/// `size` is the font height in pixel (4 pixel per row of braille),
+/// start a new line with a line break, or `\n` in slash command.
+#[command(prefix_command, slash_command)]
+pub async fn banner(
+    ctx: Context<'_>,
+    size: Option<u8>,
+    invert: Option<bool>,
+    monospace: Option<bool>,
+    #[rest] text: String,
+) -> Result<()> {
+    let text = text.trim().replace("\\n", "\n");
+    if text.is_empty() {
+        ctx.reply("Must have text to write").await?;
+        return Ok(());
+    }
+    if text.chars().count() > MAX_TEXT_LENGTH {
+        ctx.reply(format!("Text must be at most {MAX_TEXT_LENGTH} characters"))
+            .await?;
+        return Ok(());
+    }
+
+    let size = size
+        .unwrap_or(DEFAULT_FONT_SIZE)
+        .clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+    let config = BrailleConfig {
+        invert: invert.unwrap_or(false),
+        monospace: monospace.unwrap_or(false),
+        ..Default::default()
+    };
+    let rows = render(&text, size as f32, &config);
+
+    let row_fit = rows
+        .iter()
+        .all(|row| row.chars().count() < DISCORD_MESSAGE_LIMIT);
+    let pages = Paginator::new(rows.into_iter(), DISCORD_MESSAGE_LIMIT).collect::<Vec<_>>();
+    if pages.is_empty() {
+        ctx.reply("Nothing to write").await?;
+        return Ok(());
+    }
+    if !row_fit || pages.len() > MAX_MESSAGES {
+        // too many messages, send as attachment
+        let reply = CreateReply::default().attachment(CreateAttachment::bytes(
+            pages.concat().into_bytes(),
+            "banner.txt",
+        ));
+        ctx.send(reply).await?;
+        return Ok(());
+    }
+    for page in pages {
+        ctx.say(page).await?;
+    }
+    Ok(())
+}
+
+/// Draw `text` black on white with the bundled font, `px` high per line
+fn rasterize(text: &str, px: f32) -> GrayImage {
+    let mut layout = Layout::new(CoordinateSystem::PositiveYDown);
+    layout.append(&[&*FONT], &TextStyle::new(text, px, 0));
+
+    // line break is laid out as a glyph too
+    let glyphs = layout
+        .glyphs()
+        .iter()
+        .filter(|glyph| !glyph.parent.is_control())
+        .collect::<Vec<_>>();
+    let width = glyphs
+        .iter()
+        .map(|glyph| (glyph.x + glyph.width as f32).ceil().max(0.0) as u32)
+        .max()
+        .unwrap_or(0);
+    let height = layout.height().ceil() as u32;
+
+    let mut image = GrayImage::from_pixel(width, height, Luma([u8::MAX]));
+    for glyph in glyphs {
+        if glyph.width == 0 || glyph.height == 0 {
+            // whitespace
+            continue;
+        }
+        let (metrics, coverage) = FONT.rasterize_config(glyph.key);
+        let (x0, y0) = (glyph.x.round() as i64, glyph.y.round() as i64);
+        for (i, &alpha) in coverage.iter().enumerate() {
+            let x = x0 + (i % metrics.width) as i64;
+            let y = y0 + (i / metrics.width) as i64;
+            if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+                // glyph may overlap its neighbour, keep the darker one
+                let pixel = image.get_pixel_mut(x as u32, y as u32);
+                pixel.0[0] = pixel.0[0].min(u8::MAX - alpha);
+            }
+        }
+    }
+    image
+}
+
+/// Rows of braille writing `text`
+fn render(text: &str, px: f32, config: &BrailleConfig) -> Vec<String> {
+    let image = rasterize(text, px);
+    braille::image_to_patterns(&image, config)
+        .map(|row| row.collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rasterize_text() {
+        let image = rasterize("Hi", 24.0);
+        assert!(image.width() > 0);
+        assert!(image.height() >= 24);
+        assert!(image.pixels().any(|p| p.0[0] == 0), "has ink");
+        assert!(image.pixels().any(|p| p.0[0] == u8::MAX), "has background");
+    }
+
+    #[test]
+    fn font_size() {
+        let small = rasterize("A", 16.0);
+        let large = rasterize("A", 64.0);
+        assert!(large.width() > 2 * small.width());
+        assert!(large.height() > 2 * small.height());
+    }
+
+    #[test]
+    fn multi_line() {
+        let one = rasterize("abc", 24.0);
+        let two = rasterize("abc\nabc", 24.0);
+        assert_eq!(one.width(), two.width());
+        assert!(two.height() >= 2 * one.height());
+
+        let config = BrailleConfig::default();
+        let rows = render("abc\nabc", 24.0, &config);
+        assert_eq!(rows.len(), two.height().div_ceil(4) as usize);
+    }
+
+    #[test]
+    fn whitespace_only() {
+        let config = BrailleConfig::default();
+        assert!(render(" ", 24.0, &config)
+            .concat()
+            .chars()
+            .all(|c| c == '⠄'));
+    }
+
+    #[test]
+    fn invert_and_monospace() {
+        // pad partial cell with white in both case so every dot is flipped
+        let config = BrailleConfig {
+            monospace: true,
+            background: Some(u8::MAX),
+            ..Default::default()
+        };
+        let normal = render("I", 24.0, &config).concat();
+        let inverted = render(
+            "I",
+            24.0,
+            &BrailleConfig {
+                invert: true,
+                ..config.clone()
+            },
+        )
+        .concat();
+        let dots = |c: char| c as u32 - 0x2800;
+        assert!(normal.contains('⠀'), "monospace use blank braille");
+        assert_eq!(normal.chars().count(), inverted.chars().count());
+        for (a, b) in normal.chars().zip(inverted.chars()) {
+            assert_eq!(dots(a) & dots(b), 0, "{a} {b}");
+        }
+        let count = |s: &str| s.chars().map(|c| dots(c).count_ones()).sum::<u32>();
+        assert!(count(&inverted) > count(&normal));
+    }
+}
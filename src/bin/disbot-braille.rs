@@ -0,0 +1,209 @@
+//! Convert an image into braille (or other style) text without the bot
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use disbot_v2::choice::Choice;
+use disbot_v2::preprocess::Pipeline;
+use disbot_v2::render::{self, RenderOptions, Sizing};
+
+const DEFAULT_WIDTH: u32 = 59;
+const USAGE: &str = "\
+usage: disbot-braille [OPTIONS] <IMAGE>
+
+options:
+  -w, --width N           output width in characters (default 59)
+  -H, --height N          output height in rows, with --width stretch to exactly WxH
+  -b, --budget N          as large as possible within N characters (counting newline)
+  -s, --style NAME        braille, ascii, half-block, quadrant, sextant
+  -d, --dither NAME       none, floyd-steinberg, atkinson, jarvis-judice-ninke,
+                          stucki, bayer-2x2, bayer-4x4, bayer-8x8, blue-noise
+  -t, --threshold NAME    fixed, otsu, mean, adaptive
+  -c, --color NAME        none, foreground, full (ansi escape)
+  -p, --preprocess STEPS  same syntax as the unicode command, e.g. contrast=30,edge
+                          (crop, rotate and flip first)
+  -i, --invert
+  -m, --monospace
+  -o, --output FILE       write to FILE instead of stdout
+  -h, --help";
+
+#[derive(Debug)]
+struct Args {
+    image: PathBuf,
+    output: Option<PathBuf>,
+    options: RenderOptions,
+}
+
+fn choice<T: Choice>(flag: &str, value: &str) -> Result<T> {
+    T::from_name(value).ok_or_else(|| {
+        let names = T::CHOICES.iter().map(|(name, _)| name);
+        eyre!(
+            "invalid {flag} `{value}`, expected one of: {}",
+            itertools::join(names, ", ")
+        )
+    })
+}
+
+/// Parse command line arguments (without program name), `None` if help is requested
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Args>> {
+    let mut args = args.into_iter();
+    let mut image = None;
+    let mut output = None;
+    let mut options = RenderOptions {
+        style: Default::default(),
+        invert: false,
+        monospace: false,
+        threshold: Default::default(),
+        dither: Default::default(),
+        color: Default::default(),
+        preprocess: Default::default(),
+        size: Sizing::Width(DEFAULT_WIDTH),
+    };
+    let (mut width, mut height, mut budget) = (None, None, None);
+    let number = |flag: &str, value: String| {
+        value
+            .parse::<u32>()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| eyre!("invalid {flag} `{value}`"))
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| eyre!("missing value for `{arg}`"))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-i" | "--invert" => options.invert = true,
+            "-m" | "--monospace" => options.monospace = true,
+            "-w" | "--width" => width = Some(number("width", value()?)?),
+            "-H" | "--height" => height = Some(number("height", value()?)?),
+            "-b" | "--budget" => budget = Some(number("budget", value()?)?),
+            "-s" | "--style" => options.style = choice("style", &value()?)?,
+            "-d" | "--dither" => options.dither = choice("dither", &value()?)?,
+            "-t" | "--threshold" => options.threshold = choice("threshold", &value()?)?,
+            "-c" | "--color" => options.color = choice("color", &value()?)?,
+            "-p" | "--preprocess" => options.preprocess = value()?.parse::<Pipeline>()?,
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            flag if flag.starts_with('-') && flag.len() > 1 => bail!("unknown option `{flag}`"),
+            _ if image.is_some() => bail!("unexpected argument `{arg}`"),
+            _ => image = Some(PathBuf::from(arg)),
+        }
+    }
+
+    options.size = match (width, height, budget) {
+        (None, None, Some(chars)) => Sizing::Budget {
+            chars: chars as usize,
+            messages: 1,
+        },
+        (_, _, Some(_)) => bail!("--budget can't be used with --width or --height"),
+        (Some(width), Some(height), None) => Sizing::Exact { width, height },
+        (None, Some(height), None) => Sizing::Height(height),
+        (width, None, None) => Sizing::Width(width.unwrap_or(DEFAULT_WIDTH)),
+    };
+
+    let image = image.ok_or_else(|| eyre!("missing image path"))?;
+    Ok(Some(Args {
+        image,
+        output,
+        options,
+    }))
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    let image = image::open(&args.image)
+        .wrap_err_with(|| format!("cannot open {}", args.image.display()))?;
+    let rows = render::render(image, &args.options);
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).wrap_err_with(|| format!("cannot create {}", path.display()))?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    };
+    for row in rows {
+        writeln!(out, "{row}")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disbot_v2::braille::Style;
+    use disbot_v2::dither::DitherMode;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_options() {
+        let args = parse(&[
+            "-w", "30", "--style", "sextant", "-d", "atkinson", "-i", "a.png",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(args.image, PathBuf::from("a.png"));
+        assert_eq!(args.output, None);
+        assert_eq!(args.options.size, Sizing::Width(30));
+        assert_eq!(args.options.style, Style::Sextant);
+        assert_eq!(args.options.dither, DitherMode::Atkinson);
+        assert!(args.options.invert);
+        assert!(!args.options.monospace);
+    }
+
+    #[test]
+    fn parse_sizing() {
+        let size = |args: &[&str]| parse(args).unwrap().unwrap().options.size;
+        assert_eq!(size(&["a.png"]), Sizing::Width(DEFAULT_WIDTH));
+        assert_eq!(size(&["-H", "20", "a.png"]), Sizing::Height(20));
+        assert_eq!(
+            size(&["-w", "30", "-H", "20", "a.png"]),
+            Sizing::Exact {
+                width: 30,
+                height: 20
+            }
+        );
+        assert_eq!(
+            size(&["--budget", "2000", "a.png"]),
+            Sizing::Budget {
+                chars: 2000,
+                messages: 1
+            }
+        );
+        assert!(parse(&["-b", "2000", "-w", "3", "a.png"]).is_err());
+    }
+
+    #[test]
+    fn parse_help() {
+        assert!(parse(&["a.png", "--help"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["a.png", "b.png"]).is_err());
+        assert!(parse(&["-w", "0", "a.png"]).is_err());
+        assert!(parse(&["-s", "hexagon", "a.png"]).is_err());
+        assert!(parse(&["--bogus", "a.png"]).is_err());
+        assert!(parse(&["a.png", "-o"]).is_err());
+    }
+}
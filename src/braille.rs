@@ -1,4 +1,7 @@
-use image::{ImageBuffer, Pixel};
+use crate::choice::choice_enum;
+use crate::dither::DitherMode;
+use image::{GrayImage, ImageBuffer, Pixel};
+use rayon::prelude::*;
 use slice_of_array::prelude::*;
 use std::ops::Deref;
 
@@ -6,7 +9,13 @@ use std::ops::Deref;
 pub struct BrailleConfig {
     pub monospace: bool,
     pub invert: bool,
+    /// threshold used by `ThresholdMode::Fixed`
     pub threshold: u8,
+    pub threshold_mode: ThresholdMode,
+    pub dither: DitherMode,
+    /// luma of pixel outside the image, used to pad cell cut by the image edge.
+    /// `None` pad with blank (never a dot)
+    pub background: Option<u8>,
 }
 
 impl Default for BrailleConfig {
@@ -15,23 +24,43 @@ impl Default for BrailleConfig {
             monospace: false,
             invert: false,
             threshold: 125,
+            threshold_mode: ThresholdMode::default(),
+            dither: DitherMode::default(),
+            background: None,
         }
     }
 }
 
-fn pattern_to_braille(pattern: [[bool; 2]; 4], config: &BrailleConfig) -> char {
-    const SHIFT_CODE: [u8; 8] = [0, 3, 1, 4, 2, 5, 6, 7];
+impl BrailleConfig {
+    fn background(&self) -> u8 {
+        match self.background {
+            Some(background) => background,
+            None if !self.invert => u8::MAX,
+            None => u8::MIN,
+        }
+    }
+}
 
+// bit of braille codepoint for each dot in flattened pattern (row-major)
+const SHIFT_CODE: [u8; 8] = [0, 3, 1, 4, 2, 5, 6, 7];
+// stand-in for empty braille when not monospace, see `pattern_to_braille`
+const PADDING_BRAILLE: char = '⠄';
+
+fn pattern_to_braille(pattern: [[bool; 2]; 4], config: &BrailleConfig) -> char {
     let flatten = pattern.flat();
     let offset = flatten
         .iter()
         .zip(SHIFT_CODE.iter())
         .map(|(&v, sc)| (v as u32) << sc)
         .sum::<u32>();
+    offset_to_braille(offset, config)
+}
 
+// `offset` is the codepoint offset from U+2800, one bit per dot
+fn offset_to_braille(offset: u32, config: &BrailleConfig) -> char {
     // TODO: replace with uncheck version
     if !config.monospace && offset == 0 {
-        '⠄'
+        PADDING_BRAILLE
         // ' '
         // ' '
     } else {
@@ -39,6 +68,28 @@ fn pattern_to_braille(pattern: [[bool; 2]; 4], config: &BrailleConfig) -> char {
     }
 }
 
+/// Whether pixel of luma `val` is drawn as a dot
+pub fn is_dot(val: u8, config: &BrailleConfig) -> bool {
+    if !config.invert {
+        val < config.threshold
+    } else {
+        val > config.threshold
+    }
+}
+
+// luma of pixel at (x, y), or `config.background()` if outside the image
+fn luma_at<P, C>(image: &ImageBuffer<P, C>, x: u32, y: u32, config: &BrailleConfig) -> u8
+where
+    P: Pixel<Subpixel = u8> + 'static,
+    C: Deref<Target = [P::Subpixel]>,
+{
+    if x < image.width() && y < image.height() {
+        image.get_pixel(x, y).to_luma().0[0]
+    } else {
+        config.background()
+    }
+}
+
 // (x, y) = upper left coordinate
 fn extract_pattern<P, C>(
     image: &ImageBuffer<P, C>,
@@ -53,13 +104,8 @@ where
     let mut buf = [[false; 2]; 4];
 
     let put_into_buf = |buf: &mut [[bool; 2]; 4], i, j| {
-        let px = image.get_pixel(x + i, y + j);
-        let val = px.to_luma().0[0];
-        buf[j as usize][i as usize] = if !config.invert {
-            val < config.threshold
-        } else {
-            val > config.threshold
-        };
+        let val = luma_at(image, x + i, y + j, config);
+        buf[j as usize][i as usize] = is_dot(val, config);
     };
     put_into_buf(&mut buf, 0, 0);
     put_into_buf(&mut buf, 0, 1);
@@ -121,11 +167,13 @@ where
     P: Pixel<Subpixel = u8> + 'static,
     C: Deref<Target = [P::Subpixel]>,
 {
-    let w = image.width() - 1;
-    (0..w).step_by(2).map(move |x| (x, y)).map(move |(x, y)| {
-        let pattern = extract_pattern(image, x, y, config);
-        pattern_to_braille(pattern, config)
-    })
+    (0..image.width())
+        .step_by(2)
+        .map(move |x| (x, y))
+        .map(move |(x, y)| {
+            let pattern = extract_pattern(image, x, y, config);
+            pattern_to_braille(pattern, config)
+        })
     // PatternRow {
     //     image,
     //     y,
@@ -158,6 +206,9 @@ where
 //     }
 // }
 
+/// Convert image into rows of braille, each covering 2x4 pixel.
+///
+/// Cell cut by the right or bottom edge is padded with `config.background`.
 pub fn image_to_patterns<'a, P, C>(
     image: &'a ImageBuffer<P, C>,
     config: &'a BrailleConfig,
@@ -166,7 +217,7 @@ where
     P: Pixel<Subpixel = u8> + 'static,
     C: Deref<Target = [P::Subpixel]> + 'static,
 {
-    let y = (0..image.height() - 3).step_by(4);
+    let y = (0..image.height()).step_by(4);
     y.map(move |y| extract_pattern_row(image, y, config))
     // PatternIter {
     //     image,
@@ -175,22 +226,628 @@ where
     // }
 }
 
-// calculate image dimension that need to make output braille <= max_braille, will keep aspect ratio
-pub fn calculate_image_size(old_dim: (u32, u32), max_braille: usize) -> (u32, u32) {
+/// Same as `image_to_patterns` but faster, working directly on the luma buffer
+/// and converting rows in parallel
+pub fn gray_to_patterns(image: &GrayImage, config: &BrailleConfig) -> Vec<String> {
+    let (w, h) = (image.width() as usize, image.height() as usize);
+    if w == 0 {
+        return vec![String::new(); h.div_ceil(4)];
+    }
+
+    // whether pixel of each luma is a dot
+    let lut: [bool; 256] = std::array::from_fn(|val| is_dot(val as u8, config));
+    let background = lut[config.background() as usize];
+
+    image.as_raw()[..w * h]
+        .par_chunks(4 * w)
+        .map(|band| {
+            // band is up to 4 pixel rows, missing rows are padding
+            let mut rows = [None; 4];
+            rows.iter_mut()
+                .zip(band.chunks(w))
+                .for_each(|(row, pixels)| *row = Some(pixels));
+
+            let mut text = String::with_capacity(w.div_ceil(2) * '⣿'.len_utf8());
+            for x in (0..w).step_by(2) {
+                let mut offset = 0;
+                for (k, (j, i)) in itertools::iproduct!(0..4, 0..2).enumerate() {
+                    let dot = rows[j]
+                        .and_then(|row: &[u8]| row.get(x + i))
+                        .map_or(background, |&val| lut[val as usize]);
+                    offset |= (dot as u32) << SHIFT_CODE[k];
+                }
+                text.push(offset_to_braille(offset, config));
+            }
+            text
+        })
+        .collect()
+}
+
+/// Turn a block of pixel (a cell) into one character
+pub trait Renderer {
+    /// (width, height) in pixel of one cell
+    fn cell_size(&self) -> (u32, u32);
+
+    /// Whether the renderer expect dithered black & white pixel rather than grayscale
+    fn is_bilevel(&self) -> bool {
+        true
+    }
+
+    /// `cell` is the luma of each pixel in the cell, in row-major order
+    fn cell_to_char(&self, cell: &[u8], config: &BrailleConfig) -> char;
+}
+
+// largest cell of all renderers
+const MAX_CELL_PIXELS: usize = 8;
+
+/// 2x4 braille dots (U+2800..U+28FF)
+pub struct BrailleRenderer;
+
+impl Renderer for BrailleRenderer {
+    fn cell_size(&self) -> (u32, u32) {
+        (2, 4)
+    }
+
+    fn cell_to_char(&self, cell: &[u8], config: &BrailleConfig) -> char {
+        let mut pattern = [[false; 2]; 4];
+        pattern
+            .flat_mut()
+            .iter_mut()
+            .zip(cell)
+            .for_each(|(dot, &val)| *dot = is_dot(val, config));
+        pattern_to_braille(pattern, config)
+    }
+}
+
+/// ASCII luminance ramp, one character per 1x2 pixel
+pub struct AsciiRenderer;
+
+impl Renderer for AsciiRenderer {
+    fn cell_size(&self) -> (u32, u32) {
+        (1, 2)
+    }
+
+    fn is_bilevel(&self) -> bool {
+        false
+    }
+
+    fn cell_to_char(&self, cell: &[u8], config: &BrailleConfig) -> char {
+        // from least to most ink
+        const RAMP: &[u8] = b" .:-=+*#%@";
+
+        let avg = cell.iter().map(|&v| v as u32).sum::<u32>() / cell.len() as u32;
+        let ink = if !config.invert { 255 - avg } else { avg };
+        RAMP[(ink as usize * RAMP.len()) / 256] as char
+    }
+}
+
+/// Upper & lower half block, one character per 1x2 pixel
+pub struct HalfBlockRenderer;
+
+impl Renderer for HalfBlockRenderer {
+    fn cell_size(&self) -> (u32, u32) {
+        (1, 2)
+    }
+
+    fn cell_to_char(&self, cell: &[u8], config: &BrailleConfig) -> char {
+        match (is_dot(cell[0], config), is_dot(cell[1], config)) {
+            (false, false) => ' ',
+            (true, false) => '▀',
+            (false, true) => '▄',
+            (true, true) => '█',
+        }
+    }
+}
+
+// bit i of mask is set if i-th pixel of cell (row-major) is a dot
+fn cell_mask(cell: &[u8], config: &BrailleConfig) -> usize {
+    cell.iter()
+        .enumerate()
+        .map(|(i, &val)| (is_dot(val, config) as usize) << i)
+        .sum()
+}
+
+/// Quadrant block, one character per 2x2 pixel
+pub struct QuadrantRenderer;
+
+impl Renderer for QuadrantRenderer {
+    fn cell_size(&self) -> (u32, u32) {
+        (2, 2)
+    }
+
+    fn cell_to_char(&self, cell: &[u8], config: &BrailleConfig) -> char {
+        // indexed by mask of (upper left, upper right, lower left, lower right)
+        const QUADRANTS: [char; 16] = [
+            ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+        ];
+        QUADRANTS[cell_mask(cell, config)]
+    }
+}
+
+/// Unicode 13 sextant block (U+1FB00..U+1FB3B), one character per 2x3 pixel
+pub struct SextantRenderer;
+
+impl Renderer for SextantRenderer {
+    fn cell_size(&self) -> (u32, u32) {
+        (2, 3)
+    }
+
+    fn cell_to_char(&self, cell: &[u8], config: &BrailleConfig) -> char {
+        // masks that already exist as other block, so are skipped in sextant range
+        const LEFT_HALF: u32 = 0b010101;
+        const RIGHT_HALF: u32 = 0b101010;
+
+        match cell_mask(cell, config) as u32 {
+            0 => ' ',
+            LEFT_HALF => '▌',
+            RIGHT_HALF => '▐',
+            0b111111 => '█',
+            mask => {
+                let skipped = (mask > LEFT_HALF) as u32 + (mask > RIGHT_HALF) as u32;
+                char::from_u32(0x1FB00 + mask - 1 - skipped)
+                    .expect("to always be in range of valid unicode")
+            }
+        }
+    }
+}
+
+choice_enum! {
+    /// Character set used to draw the image
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum Style {
+        #[default]
+        Braille = "braille",
+        Ascii = "ascii",
+        HalfBlock = "half-block",
+        Quadrant = "quadrant",
+        Sextant = "sextant",
+    }
+}
+
+impl Style {
+    pub fn renderer(self) -> &'static dyn Renderer {
+        match self {
+            Style::Braille => &BrailleRenderer,
+            Style::Ascii => &AsciiRenderer,
+            Style::HalfBlock => &HalfBlockRenderer,
+            Style::Quadrant => &QuadrantRenderer,
+            Style::Sextant => &SextantRenderer,
+        }
+    }
+}
+
+/// Like `image_to_patterns` but with any renderer
+pub fn image_to_text<'a, P, C>(
+    image: &'a ImageBuffer<P, C>,
+    renderer: &'a dyn Renderer,
+    config: &'a BrailleConfig,
+) -> impl Iterator<Item = impl Iterator<Item = char> + 'a> + 'a
+where
+    P: Pixel<Subpixel = u8> + 'static,
+    C: Deref<Target = [P::Subpixel]> + 'static,
+{
+    let (cw, ch) = renderer.cell_size();
+    let y = (0..image.height()).step_by(ch as usize);
+    y.map(move |y| {
+        let x = (0..image.width()).step_by(cw as usize);
+        x.map(move |x| {
+            let mut cell = [0; MAX_CELL_PIXELS];
+            for (k, (j, i)) in itertools::iproduct!(0..ch, 0..cw).enumerate() {
+                cell[k] = luma_at(image, x + i, y + j, config);
+            }
+            renderer.cell_to_char(&cell[..(cw * ch) as usize], config)
+        })
+    })
+}
+
+/// Inverse of `pattern_to_braille`, `None` if `c` is not a braille character.
+///
+/// When `config.monospace` is false, padding braille is decoded as an empty pattern.
+fn braille_to_pattern(c: char, config: &BrailleConfig) -> Option<[[bool; 2]; 4]> {
+    if !config.monospace && c == PADDING_BRAILLE {
+        return Some([[false; 2]; 4]);
+    }
+
+    let offset = (c as u32).checked_sub(0x2800).filter(|&o| o <= 0xFF)?;
+    let mut pattern = [[false; 2]; 4];
+    pattern
+        .flat_mut()
+        .iter_mut()
+        .zip(SHIFT_CODE.iter())
+        .for_each(|(v, sc)| *v = (offset >> sc) & 1 == 1);
+    Some(pattern)
+}
+
+/// Braille character with `dots` raised, using the standard numbering:
+/// 1-3 down the left column, 4-6 down the right, 7 and 8 on the bottom row.
+///
+/// Panics if a dot is not in `1..=8`.
+pub fn dots_to_braille(dots: &[u8]) -> char {
+    let mut pattern = [[false; 2]; 4];
+    for &dot in dots {
+        let (row, col) = match dot {
+            1..=3 => (dot - 1, 0),
+            4..=6 => (dot - 4, 1),
+            7 | 8 => (3, dot - 7),
+            _ => panic!("braille has no dot {dot}"),
+        };
+        pattern[row as usize][col as usize] = true;
+    }
+    let config = BrailleConfig {
+        monospace: true,
+        ..Default::default()
+    };
+    pattern_to_braille(pattern, &config)
+}
+
+/// Width and height of the image [`patterns_to_image`] makes from `text`
+pub fn patterns_size(text: &str) -> (usize, usize) {
+    let n_col = text.lines().map(|l| l.chars().count()).max().unwrap_or(0);
+    (2 * n_col, 4 * text.lines().count())
+}
+
+/// Decode braille text back into a bilevel image, dot is black (white if `config.invert`).
+///
+/// Each line is a row of braille, shorter line and non-braille character are left blank.
+pub fn patterns_to_image(text: &str, config: &BrailleConfig) -> GrayImage {
+    let (dot, blank) = if !config.invert { (0, 255) } else { (255, 0) };
+
+    let (width, height) = patterns_size(text);
+    let mut image = GrayImage::from_pixel(width as u32, height as u32, image::Luma([blank]));
+
+    for (row, line) in text.lines().enumerate() {
+        for (col, c) in line.chars().enumerate() {
+            let Some(pattern) = braille_to_pattern(c, config) else {
+                continue;
+            };
+            for (j, i) in itertools::iproduct!(0..4, 0..2) {
+                if pattern[j][i] {
+                    let (x, y) = (2 * col as u32 + i as u32, 4 * row as u32 + j as u32);
+                    image.put_pixel(x, y, image::Luma([dot]));
+                }
+            }
+        }
+    }
+    image
+}
+
+choice_enum! {
+    /// How to choose the threshold that separate dark & bright pixel
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum ThresholdMode {
+        /// Use `BrailleConfig::threshold` as is
+        #[default]
+        Fixed = "fixed",
+        /// Global threshold that best separate the histogram into two classes
+        Otsu = "otsu",
+        /// Global threshold at the average brightness
+        Mean = "mean",
+        /// Threshold each pixel against the average brightness of its surrounding tile
+        Adaptive = "adaptive",
+    }
+}
+
+/// Threshold for each pixel, pixel below it is dark
+pub enum Threshold {
+    Global(u8),
+    Local(GrayImage),
+}
+
+impl Threshold {
+    pub fn at(&self, x: u32, y: u32) -> u8 {
+        match self {
+            Threshold::Global(t) => *t,
+            Threshold::Local(map) => map.get_pixel(x, y).0[0],
+        }
+    }
+}
+
+// side length of the tile used by adaptive threshold, in pixel
+const ADAPTIVE_TILE: u32 = 16;
+// adaptive threshold is slightly below local mean so flat area stay bright
+const ADAPTIVE_OFFSET: u8 = 5;
+
+pub fn compute_threshold(image: &GrayImage, config: &BrailleConfig) -> Threshold {
+    match config.threshold_mode {
+        ThresholdMode::Fixed => Threshold::Global(config.threshold),
+        ThresholdMode::Otsu => Threshold::Global(otsu_threshold(image)),
+        ThresholdMode::Mean => Threshold::Global(mean_threshold(image)),
+        ThresholdMode::Adaptive => Threshold::Local(adaptive_threshold(image)),
+    }
+}
+
+fn histogram(image: &GrayImage) -> [u64; 256] {
+    let mut hist = [0; 256];
+    image.as_raw().iter().for_each(|&v| hist[v as usize] += 1);
+    hist
+}
+
+fn otsu_threshold(image: &GrayImage) -> u8 {
+    let hist = histogram(image);
+    let total = hist.iter().sum::<u64>() as f64;
+    let total_sum = hist
+        .iter()
+        .enumerate()
+        .map(|(v, &n)| v as f64 * n as f64)
+        .sum::<f64>();
+
+    // dark class is [0, t), bright class is [t, 256)
+    let (mut best_t, mut best_var) = (128, 0.0);
+    let (mut dark_n, mut dark_sum) = (0.0, 0.0);
+    for t in 1..256 {
+        dark_n += hist[t - 1] as f64;
+        dark_sum += (t - 1) as f64 * hist[t - 1] as f64;
+        let bright_n = total - dark_n;
+        if dark_n == 0.0 || bright_n == 0.0 {
+            continue;
+        }
+
+        let mean_diff = dark_sum / dark_n - (total_sum - dark_sum) / bright_n;
+        let between_var = dark_n * bright_n * mean_diff * mean_diff;
+        if between_var > best_var {
+            (best_t, best_var) = (t as u8, between_var);
+        }
+    }
+    best_t
+}
+
+fn mean_threshold(image: &GrayImage) -> u8 {
+    let pixels = image.as_raw();
+    if pixels.is_empty() {
+        return 128;
+    }
+    let sum = pixels.iter().map(|&v| v as u64).sum::<u64>();
+    (sum / pixels.len() as u64).max(1) as u8
+}
+
+fn adaptive_threshold(image: &GrayImage) -> GrayImage {
+    let (w, h) = image.dimensions();
+
+    // integral[y][x] = sum of pixel in [0, x) x [0, y)
+    let stride = (w + 1) as usize;
+    let mut integral = vec![0u64; stride * (h + 1) as usize];
+    for y in 0..h as usize {
+        let mut row_sum = 0;
+        for x in 0..w as usize {
+            row_sum += image.as_raw()[y * w as usize + x] as u64;
+            integral[(y + 1) * stride + x + 1] = integral[y * stride + x + 1] + row_sum;
+        }
+    }
+
+    let r = ADAPTIVE_TILE / 2;
+    GrayImage::from_fn(w, h, |x, y| {
+        let (x0, y0) = (x.saturating_sub(r) as usize, y.saturating_sub(r) as usize);
+        let (x1, y1) = ((x + r + 1).min(w) as usize, (y + r + 1).min(h) as usize);
+        let sum = integral[y1 * stride + x1] + integral[y0 * stride + x0]
+            - integral[y0 * stride + x1]
+            - integral[y1 * stride + x0];
+        let mean = sum / ((x1 - x0) * (y1 - y0)) as u64;
+        image::Luma([(mean as u8).saturating_sub(ADAPTIVE_OFFSET).max(1)])
+    })
+}
+
+/// Image dimension (in pixel) for output `cols` character wide, keeping aspect ratio
+pub fn fit_width(old_dim: (u32, u32), cell_size: (u32, u32), cols: u32) -> (u32, u32) {
+    let ((w, h), (cw, ch)) = (old_dim, cell_size);
+    let w2 = cw * cols;
+    // a character is about twice as tall as it is wide
+    // so pixel of a cell isn't square unless ch = 2 * cw
+    let h2 = (h as u64 * w2 as u64 * ch as u64) / (w as u64 * 2 * cw as u64);
+    (w2, h2 as u32)
+}
+
+/// Calculate image dimension (in pixel) that make output <= `max_chars` characters,
+/// counting the newline after each row. Will keep aspect ratio
+pub fn calculate_image_size(
+    old_dim: (u32, u32),
+    cell_size: (u32, u32),
+    max_chars: usize,
+) -> (u32, u32) {
     let (w, h) = old_dim;
-    let scaler = ((8 * max_braille) as f32 / (w * h) as f32).sqrt().min(4.0);
+    if w == 0 || h == 0 {
+        return (0, 0);
+    }
 
-    let w2 = (scaler * w as f32) as u32;
-    let h2 = (scaler * h as f32) as u32;
+    let fit = |cols| {
+        let (_, h2) = fit_width(old_dim, cell_size, cols);
+        let rows = h2.div_ceil(cell_size.1);
+        rows as usize * (cols as usize + 1) <= max_chars
+    };
+    // a row and its newline must fit
+    let max_cols = max_chars.saturating_sub(1).min(u32::MAX as usize) as u32;
 
-    (w2, h2)
+    // rows ~= ratio * cols, so output ~= ratio * cols * (cols + 1)
+    let ratio = h as f64 / (2 * w) as f64;
+    let estimate = ((1.0 + 4.0 * max_chars as f64 / ratio).sqrt() - 1.0) / 2.0;
+    // estimate is off by a bit from rounding
+    let mut cols = (estimate as u32).min(max_cols);
+    while cols > 0 && !fit(cols) {
+        cols -= 1;
+    }
+    while cols < max_cols && fit(cols + 1) {
+        cols += 1;
+    }
+    fit_width(old_dim, cell_size, cols)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::OpenOptions;
-    use std::io::{BufWriter, Write};
+    use crate::golden;
+    use rand::prelude::*;
+
+    #[test]
+    fn otsu_split_bimodal() {
+        let image = GrayImage::from_fn(32, 32, |x, _| image::Luma([if x < 16 { 40 } else { 200 }]));
+        let t = otsu_threshold(&image);
+        assert!((41..=200).contains(&t), "{t}");
+    }
+
+    #[test]
+    fn mean_of_uniform() {
+        let image = GrayImage::from_pixel(8, 8, image::Luma([77]));
+        assert_eq!(mean_threshold(&image), 77);
+    }
+
+    #[test]
+    fn adaptive_follow_local_brightness() {
+        // dark line on a left-to-right gradient, too wide for any global threshold
+        let image = GrayImage::from_fn(128, 32, |x, y| {
+            let bg = 60 + x as u8;
+            image::Luma([if y == 16 { bg - 50 } else { bg }])
+        });
+        let threshold = adaptive_threshold(&image);
+        for x in 0..128 {
+            assert!(image.get_pixel(x, 16).0[0] < threshold.get_pixel(x, 16).0[0]);
+            assert!(image.get_pixel(x, 4).0[0] >= threshold.get_pixel(x, 4).0[0]);
+        }
+    }
+
+    #[test]
+    fn dot_numbering() {
+        assert_eq!(dots_to_braille(&[]), '⠀');
+        assert_eq!(dots_to_braille(&[1]), '⠁');
+        assert_eq!(dots_to_braille(&[1, 2, 4, 5]), '⠛');
+        assert_eq!(dots_to_braille(&[3, 4, 5, 6]), '⠼');
+        assert_eq!(dots_to_braille(&[7, 8]), '⣀');
+        assert_eq!(dots_to_braille(&[1, 2, 3, 4, 5, 6, 7, 8]), '⣿');
+    }
+
+    #[test]
+    fn btp_roundtrip() {
+        let config = BrailleConfig {
+            monospace: true,
+            ..Default::default()
+        };
+        for offset in 0..=0xFF_u32 {
+            let c = char::from_u32(0x2800 + offset).unwrap();
+            let pattern = braille_to_pattern(c, &config).unwrap();
+            assert_eq!(pattern_to_braille(pattern, &config), c);
+        }
+        assert_eq!(braille_to_pattern('a', &config), None);
+    }
+
+    #[test]
+    fn btp_padding() {
+        assert_eq!(
+            braille_to_pattern(PADDING_BRAILLE, &Default::default()),
+            Some([[false; 2]; 4])
+        );
+        assert_eq!(
+            braille_to_pattern(
+                PADDING_BRAILLE,
+                &BrailleConfig {
+                    monospace: true,
+                    ..Default::default()
+                }
+            ),
+            Some([
+                [false, false],
+                [false, false],
+                [true, false],
+                [false, false]
+            ])
+        );
+    }
+
+    #[test]
+    fn pti_roundtrip() {
+        let image = GrayImage::from_fn(16, 12, |x, y| {
+            image::Luma([if (x * 7 + y * 3) % 5 < 2 { 0 } else { 255 }])
+        });
+        // monospace so lone dot 3 isn't confused with padding
+        let config = BrailleConfig {
+            monospace: true,
+            ..Default::default()
+        };
+        let text = image_to_patterns(&image, &config)
+            .map(|row| row.collect::<String>() + "\n")
+            .collect::<String>();
+        assert_eq!(patterns_to_image(&text, &config), image);
+    }
+
+    #[test]
+    fn pti_ragged_lines() {
+        let image = patterns_to_image("⣿⣿\nx⣿\n", &Default::default());
+        assert_eq!(image.dimensions(), (4, 8));
+        assert_eq!(patterns_size("⣿⣿\nx⣿\n"), (4, 8));
+        assert_eq!(image.get_pixel(0, 4).0[0], 255);
+        assert_eq!(image.get_pixel(2, 4).0[0], 0);
+    }
+
+    fn render_text(image: &GrayImage, style: Style, config: &BrailleConfig) -> Vec<String> {
+        image_to_text(image, style.renderer(), config)
+            .map(|row| row.collect())
+            .collect()
+    }
+
+    #[test]
+    fn braille_renderer_match_image_to_patterns() {
+        let image = GrayImage::from_fn(10, 12, |x, y| {
+            image::Luma([if (x * 5 + y * 3) % 7 < 3 { 0 } else { 255 }])
+        });
+        let config = BrailleConfig::default();
+        let expected = image_to_patterns(&image, &config)
+            .map(|row| row.collect::<String>())
+            .collect::<Vec<_>>();
+        assert_eq!(render_text(&image, Style::Braille, &config), expected);
+    }
+
+    #[test]
+    fn block_renderers() {
+        // left column dark, right column bright
+        let image = GrayImage::from_fn(2, 6, |x, _| image::Luma([if x == 0 { 0 } else { 255 }]));
+        let config = BrailleConfig::default();
+        assert_eq!(
+            render_text(&image, Style::HalfBlock, &config),
+            ["█ ", "█ ", "█ "]
+        );
+        assert_eq!(
+            render_text(&image, Style::Quadrant, &config),
+            ["▌", "▌", "▌"]
+        );
+        assert_eq!(render_text(&image, Style::Sextant, &config), ["▌", "▌"]);
+    }
+
+    #[test]
+    fn quadrant_chars() {
+        let config = BrailleConfig::default();
+        let quadrant = |cell: [u8; 4]| QuadrantRenderer.cell_to_char(&cell, &config);
+        assert_eq!(quadrant([255, 255, 255, 255]), ' ');
+        assert_eq!(quadrant([0, 255, 255, 255]), '▘');
+        assert_eq!(quadrant([255, 0, 0, 255]), '▞');
+        assert_eq!(quadrant([0, 0, 0, 255]), '▛');
+        assert_eq!(quadrant([0, 0, 0, 0]), '█');
+    }
+
+    #[test]
+    fn sextant_chars() {
+        let config = BrailleConfig::default();
+        let sextant = |mask: u32| {
+            let cell: [u8; 6] = std::array::from_fn(|i| if mask >> i & 1 == 1 { 0 } else { 255 });
+            SextantRenderer.cell_to_char(&cell, &config)
+        };
+        assert_eq!(sextant(0b000000), ' ');
+        assert_eq!(sextant(0b000001), '\u{1FB00}');
+        assert_eq!(sextant(0b010100), '\u{1FB13}');
+        assert_eq!(sextant(0b010101), '▌');
+        assert_eq!(sextant(0b010110), '\u{1FB14}');
+        assert_eq!(sextant(0b101010), '▐');
+        assert_eq!(sextant(0b111110), '\u{1FB3B}');
+        assert_eq!(sextant(0b111111), '█');
+    }
+
+    #[test]
+    fn ascii_ramp() {
+        let config = BrailleConfig::default();
+        assert_eq!(AsciiRenderer.cell_to_char(&[255, 255], &config), ' ');
+        assert_eq!(AsciiRenderer.cell_to_char(&[0, 0], &config), '@');
+        let inverted = BrailleConfig {
+            invert: true,
+            ..Default::default()
+        };
+        assert_eq!(AsciiRenderer.cell_to_char(&[0, 0], &inverted), ' ');
+    }
 
     #[test]
     fn ptb() {
@@ -200,7 +857,8 @@ mod tests {
                 &BrailleConfig {
                     monospace: false,
                     threshold: 125,
-                    invert: false
+                    invert: false,
+                    ..Default::default()
                 }
             ),
             '⠄'
@@ -211,7 +869,8 @@ mod tests {
                 &BrailleConfig {
                     monospace: true,
                     threshold: 125,
-                    invert: false
+                    invert: false,
+                    ..Default::default()
                 }
             ),
             '⠀'
@@ -237,44 +896,180 @@ mod tests {
         );
     }
 
-    fn itp() {
-        let mut img = image::open("./sample_croped.jpg").unwrap().into_luma8();
-        let (w, h) = img.dimensions();
+    #[test]
+    fn golden_patterns() {
+        let configs = [
+            ("default", BrailleConfig::default()),
+            (
+                "invert-padded",
+                BrailleConfig {
+                    invert: true,
+                    monospace: false,
+                    ..Default::default()
+                },
+            ),
+        ];
+        for sample in golden::SAMPLES {
+            let image = image::open(sample).unwrap();
+            let (w, h) = (image.width(), image.height());
+            let image = image
+                .resize_exact(80, h * 80 / w, image::imageops::FilterType::CatmullRom)
+                .to_luma8();
+
+            for (name, config) in &configs {
+                let mut text = String::new();
+                image_to_patterns(&image, config).for_each(|row| {
+                    text.extend(row);
+                    text.push('\n');
+                });
+                golden::assert_golden(
+                    &format!("patterns/{}-{name}.txt", golden::sample_name(sample)),
+                    &text,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pad_partial_cells() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let config = BrailleConfig {
+            monospace: true,
+            ..Default::default()
+        };
+
+        for (w, h) in itertools::iproduct!(0..11, 0..11) {
+            let image = GrayImage::from_fn(w, h, |_, _| {
+                image::Luma([if rng.random() { 0 } else { 255 }])
+            });
+
+            let rows = image_to_patterns(&image, &config)
+                .map(|row| row.collect::<String>())
+                .collect::<Vec<_>>();
+            assert_eq!(rows.len() as u32, h.div_ceil(4), "{w}x{h}");
+            for row in &rows {
+                assert_eq!(row.chars().count() as u32, w.div_ceil(2), "{w}x{h}");
+            }
+
+            // every pixel survive, padding is blank
+            let decoded = patterns_to_image(&rows.join("\n"), &config);
+            for (x, y, px) in decoded.enumerate_pixels() {
+                let expected = if x < w && y < h {
+                    image.get_pixel(x, y).0[0]
+                } else {
+                    255
+                };
+                assert_eq!(px.0[0], expected, "{w}x{h} at ({x}, {y})");
+            }
+
+            for style in [
+                Style::Braille,
+                Style::Ascii,
+                Style::HalfBlock,
+                Style::Quadrant,
+                Style::Sextant,
+            ] {
+                let (cw, ch) = style.renderer().cell_size();
+                let rows = render_text(&image, style, &config);
+                assert_eq!(rows.len() as u32, h.div_ceil(ch), "{style:?} {w}x{h}");
+                for row in &rows {
+                    assert_eq!(
+                        row.chars().count() as u32,
+                        w.div_ceil(cw),
+                        "{style:?} {w}x{h}"
+                    );
+                }
+            }
+        }
+    }
 
+    #[test]
+    fn configurable_background() {
+        // 1x1 dot, the other 7 pixel of the cell are padding
+        let image = GrayImage::from_pixel(1, 1, image::Luma([0]));
+        let render = |config: &BrailleConfig| {
+            image_to_patterns(&image, config)
+                .map(|row| row.collect::<String>())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(render(&BrailleConfig::default()), ["⠁"]);
+        let config = BrailleConfig {
+            background: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(render(&config), ["⣿"]);
+
+        // inverted, the pixel is now blank and so is the padding
         let config = BrailleConfig {
-            monospace: false,
-            threshold: 125,
             invert: true,
+            monospace: true,
+            ..Default::default()
         };
+        assert_eq!(render(&config), ["⠀"]);
+    }
 
-        let target = 50000;
-        let scaler = ((8 * target) as f32 / (w * h) as f32).sqrt().min(4.0);
+    #[test]
+    fn fast_path_match_generic() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let configs = [
+            BrailleConfig::default(),
+            BrailleConfig {
+                invert: true,
+                monospace: true,
+                threshold: 40,
+                ..Default::default()
+            },
+            BrailleConfig {
+                background: Some(0),
+                ..Default::default()
+            },
+        ];
 
-        let w2 = (scaler * w as f32) as u32;
-        let h2 = (scaler * h as f32) as u32;
+        for (w, h) in itertools::iproduct!(0..11, 0..11) {
+            let image = GrayImage::from_fn(w, h, |_, _| image::Luma([rng.random()]));
+            for config in &configs {
+                let expected = image_to_patterns(&image, config)
+                    .map(|row| row.collect::<String>())
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    gray_to_patterns(&image, config),
+                    expected,
+                    "{w}x{h} {config:?}"
+                );
+            }
+        }
+    }
 
-        let mut img =
-            image::imageops::resize(&img, w2, h2, image::imageops::FilterType::CatmullRom);
+    #[test]
+    fn image_size_within_budget() {
+        for (dim, budget) in itertools::iproduct!(
+            [
+                (100, 100),
+                (640, 480),
+                (480, 640),
+                (1000, 10),
+                (10, 1000),
+                (1, 1)
+            ],
+            [0, 1, 59, 2000, 10_000]
+        ) {
+            for style in [Style::Braille, Style::Ascii, Style::Sextant] {
+                let cell_size = style.renderer().cell_size();
+                let (w2, h2) = calculate_image_size(dim, cell_size, budget);
+                let (cols, rows) = (w2.div_ceil(cell_size.0), h2.div_ceil(cell_size.1));
+                let chars = rows as usize * (cols as usize + 1);
+                assert!(chars <= budget, "{dim:?} {budget} {style:?}: {chars}");
 
-        if true {
-            image::imageops::dither(&mut img, &image::imageops::BiLevel)
+                // one more column would go over budget
+                let (_, h3) = fit_width(dim, cell_size, cols + 1);
+                let (cols, rows) = (cols + 1, h3.div_ceil(cell_size.1));
+                let chars = rows as usize * (cols as usize + 1);
+                assert!(
+                    chars > budget || cols + 1 > budget as u32,
+                    "{dim:?} {budget} {style:?}"
+                );
+            }
         }
-
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open("./text.txt")
-            .unwrap();
-        let mut buffer = BufWriter::new(file);
-
-        let mut buf = [0; 4];
-        image_to_patterns(&img, &config).for_each(|row| {
-            row.for_each(|val| {
-                let v = val.encode_utf8(&mut buf);
-                buffer.write_all(v.as_bytes()).unwrap();
-            });
-            buffer.write(b"\n").unwrap();
-        });
     }
 }
@@ -0,0 +1,74 @@
+//! Named enum options, parsed from the command line or picked as discord command choices
+//!
+//! The poise `ChoiceParameter` derive is only added with the `bot` feature, so the cli doesn't
+//! pull in the discord framework.
+
+/// Enum option selectable by its name
+pub trait Choice: Copy + PartialEq + 'static {
+    /// Every option with its name, in declaration order
+    const CHOICES: &'static [(&'static str, Self)];
+
+    fn name(self) -> &'static str {
+        Self::CHOICES
+            .iter()
+            .find(|(_, choice)| *choice == self)
+            .map(|(name, _)| *name)
+            .expect("every variant to be listed")
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::CHOICES
+            .iter()
+            .find(|(choice, _)| *choice == name)
+            .map(|(_, choice)| *choice)
+    }
+}
+
+/// Declare an enum implementing [`Choice`], written as `Variant = "name"`
+macro_rules! choice_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $enum:ident {
+            $($(#[$variant_meta:meta])* $variant:ident = $name:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[cfg_attr(feature = "bot", derive(poise::ChoiceParameter))]
+        $vis enum $enum {
+            $(
+                $(#[$variant_meta])*
+                #[cfg_attr(feature = "bot", name = $name)]
+                $variant,
+            )*
+        }
+
+        impl $crate::choice::Choice for $enum {
+            const CHOICES: &'static [(&'static str, Self)] = &[$(($name, Self::$variant)),*];
+        }
+    };
+}
+pub(crate) use choice_enum;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ansi::ColorMode;
+    use crate::braille::{Style, ThresholdMode};
+    use crate::dither::DitherMode;
+
+    fn round_trip<T: Choice + std::fmt::Debug>() {
+        for &(name, choice) in T::CHOICES {
+            assert_eq!(choice.name(), name);
+            assert_eq!(T::from_name(name), Some(choice));
+        }
+        assert_eq!(T::from_name("nonexistent"), None);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        round_trip::<ColorMode>();
+        round_trip::<Style>();
+        round_trip::<ThresholdMode>();
+        round_trip::<DitherMode>();
+    }
+}
@@ -0,0 +1,158 @@
+use image::error::LimitErrorKind;
+use image::{DynamicImage, ImageError, ImageReader};
+use std::env;
+use std::io::Cursor;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Image is too large (max {max_dimension}x{max_dimension} pixels)")]
+    Dimension { max_dimension: u32 },
+    #[error("Image needs too much memory to decode (max {} MiB)", .max_alloc >> 20)]
+    Memory { max_alloc: u64 },
+    #[error("Image took longer than {} seconds to decode", .timeout.as_secs())]
+    Timeout { timeout: Duration },
+    #[error("Cannot decode image: {0}")]
+    Image(ImageError),
+}
+
+/// Limits for decoding untrusted images
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// size of the encoded image
+    pub max_bytes: usize,
+    /// width and height of the decoded image
+    pub max_dimension: u32,
+    /// memory the decoder may allocate
+    pub max_alloc: u64,
+    pub timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 8 << 20,
+            max_dimension: 4096,
+            max_alloc: 256 << 20,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{key} envar should be a number")),
+        Err(_) => default,
+    }
+}
+
+impl Limits {
+    /// Default limits, overridden by `IMAGE_MAX_BYTES`, `IMAGE_MAX_DIMENSION`,
+    /// `IMAGE_MAX_ALLOC` (bytes) and `IMAGE_DECODE_TIMEOUT` (seconds) envar
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            max_bytes: env_or("IMAGE_MAX_BYTES", default.max_bytes),
+            max_dimension: env_or("IMAGE_MAX_DIMENSION", default.max_dimension),
+            max_alloc: env_or("IMAGE_MAX_ALLOC", default.max_alloc),
+            timeout: Duration::from_secs(env_or("IMAGE_DECODE_TIMEOUT", default.timeout.as_secs())),
+        }
+    }
+
+    pub fn image_limits(&self) -> image::Limits {
+        let mut limits = image::Limits::default();
+        limits.max_image_width = Some(self.max_dimension);
+        limits.max_image_height = Some(self.max_dimension);
+        limits.max_alloc = Some(self.max_alloc);
+        limits
+    }
+
+    /// Turn limit violation into a more specific error
+    pub fn error(&self, error: ImageError) -> Error {
+        match &error {
+            ImageError::Limits(e) => match e.kind() {
+                LimitErrorKind::DimensionError => Error::Dimension {
+                    max_dimension: self.max_dimension,
+                },
+                LimitErrorKind::InsufficientMemory => Error::Memory {
+                    max_alloc: self.max_alloc,
+                },
+                _ => Error::Image(error),
+            },
+            _ => Error::Image(error),
+        }
+    }
+}
+
+/// Decode a still image (first frame of an animation) within `limits`
+pub fn decode(data: &[u8], limits: &Limits) -> Result<DynamicImage, Error> {
+    let decode = || {
+        let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+        reader.limits(limits.image_limits());
+        reader.decode()
+    };
+    decode().map_err(|e| limits.error(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::ImageFormat;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = Vec::new();
+        DynamicImage::new_luma8(width, height)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn within_limits() {
+        let image = decode(&png(40, 30), &Limits::default()).unwrap();
+        assert_eq!((image.width(), image.height()), (40, 30));
+    }
+
+    #[test]
+    fn reject_large_dimension() {
+        let limits = Limits {
+            max_dimension: 64,
+            ..Default::default()
+        };
+        assert!(matches!(
+            decode(&png(65, 1), &limits),
+            Err(Error::Dimension { max_dimension: 64 })
+        ));
+        assert!(matches!(
+            decode(&png(1, 65), &limits),
+            Err(Error::Dimension { max_dimension: 64 })
+        ));
+    }
+
+    #[test]
+    fn reject_large_allocation() {
+        // a mostly blank png compress really well, but still need the full buffer to decode
+        let limits = Limits {
+            max_alloc: 1 << 20,
+            ..Default::default()
+        };
+        let bomb = png(2048, 2048);
+        assert!(bomb.len() < 1 << 16);
+        assert!(matches!(
+            decode(&bomb, &limits),
+            Err(Error::Memory { max_alloc: 1048576 })
+        ));
+    }
+
+    #[test]
+    fn reject_garbage() {
+        assert!(matches!(
+            decode(b"not an image", &Limits::default()),
+            Err(Error::Image(_))
+        ));
+    }
+}
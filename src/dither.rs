@@ -0,0 +1,400 @@
+use crate::braille::Threshold;
+use crate::choice::choice_enum;
+use image::{GrayImage, Luma};
+use std::sync::LazyLock;
+
+choice_enum! {
+    /// Algorithm used to reduce grayscale image into black (0) & white (255)
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum DitherMode {
+        /// No dithering, pixel is compared against the threshold as is
+        None = "none",
+        #[default]
+        FloydSteinberg = "floyd-steinberg",
+        Atkinson = "atkinson",
+        JarvisJudiceNinke = "jarvis-judice-ninke",
+        Stucki = "stucki",
+        Bayer2 = "bayer-2x2",
+        Bayer4 = "bayer-4x4",
+        Bayer8 = "bayer-8x8",
+        BlueNoise = "blue-noise",
+    }
+}
+
+/// Error diffusion kernel, each entry is (dx, dy, weight) where error * weight / divisor is
+/// pushed to pixel (x + dx, y + dy)
+struct Kernel {
+    divisor: i32,
+    weights: &'static [(i32, u32, i32)],
+}
+
+const FLOYD_STEINBERG: Kernel = Kernel {
+    divisor: 16,
+    weights: &[(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)],
+};
+
+// atkinson only diffuse 6/8 of the error, which give more contrast
+const ATKINSON: Kernel = Kernel {
+    divisor: 8,
+    weights: &[
+        (1, 0, 1),
+        (2, 0, 1),
+        (-1, 1, 1),
+        (0, 1, 1),
+        (1, 1, 1),
+        (0, 2, 1),
+    ],
+};
+
+const JARVIS_JUDICE_NINKE: Kernel = Kernel {
+    divisor: 48,
+    weights: &[
+        (1, 0, 7),
+        (2, 0, 5),
+        (-2, 1, 3),
+        (-1, 1, 5),
+        (0, 1, 7),
+        (1, 1, 5),
+        (2, 1, 3),
+        (-2, 2, 1),
+        (-1, 2, 3),
+        (0, 2, 5),
+        (1, 2, 3),
+        (2, 2, 1),
+    ],
+};
+
+const STUCKI: Kernel = Kernel {
+    divisor: 42,
+    weights: &[
+        (1, 0, 8),
+        (2, 0, 4),
+        (-2, 1, 2),
+        (-1, 1, 4),
+        (0, 1, 8),
+        (1, 1, 4),
+        (2, 1, 2),
+        (-2, 2, 1),
+        (-1, 2, 2),
+        (0, 2, 4),
+        (1, 2, 2),
+        (2, 2, 1),
+    ],
+};
+
+/// Dither image in place, after this every pixel is either 0 or 255.
+///
+/// Pixel below `threshold` become black. Ordered modes (bayer, blue noise) spread it
+/// by their threshold map, centered on it.
+pub fn dither(image: &mut GrayImage, mode: DitherMode, threshold: &Threshold) {
+    match mode {
+        DitherMode::None => image
+            .enumerate_pixels_mut()
+            .for_each(|(x, y, px)| px.0[0] = quantize(px.0[0] as i32, threshold.at(x, y))),
+        DitherMode::FloydSteinberg => error_diffusion(image, &FLOYD_STEINBERG, threshold),
+        DitherMode::Atkinson => error_diffusion(image, &ATKINSON, threshold),
+        DitherMode::JarvisJudiceNinke => error_diffusion(image, &JARVIS_JUDICE_NINKE, threshold),
+        DitherMode::Stucki => error_diffusion(image, &STUCKI, threshold),
+        DitherMode::Bayer2 => ordered(image, 2, |x, y| bayer(x, y, 2), threshold),
+        DitherMode::Bayer4 => ordered(image, 4, |x, y| bayer(x, y, 4), threshold),
+        DitherMode::Bayer8 => ordered(image, 8, |x, y| bayer(x, y, 8), threshold),
+        DitherMode::BlueNoise => ordered(
+            image,
+            BLUE_NOISE_SIZE as u32,
+            |x, y| BLUE_NOISE[y as usize * BLUE_NOISE_SIZE + x as usize] as u32,
+            threshold,
+        ),
+    }
+}
+
+fn quantize(val: i32, threshold: u8) -> u8 {
+    if val < threshold as i32 {
+        0
+    } else {
+        255
+    }
+}
+
+fn error_diffusion(image: &mut GrayImage, kernel: &Kernel, threshold: &Threshold) {
+    let (w, h) = image.dimensions();
+    let mut buf = image.as_raw().iter().map(|&v| v as i32).collect::<Vec<_>>();
+
+    for y in 0..h {
+        for x in 0..w {
+            let i = (y * w + x) as usize;
+            let old = buf[i];
+            let new = quantize(old, threshold.at(x, y));
+            image.put_pixel(x, y, Luma([new]));
+
+            let err = old - new as i32;
+            for &(dx, dy, weight) in kernel.weights {
+                let (nx, ny) = (x as i32 + dx, y + dy);
+                if nx < 0 || nx >= w as i32 || ny >= h {
+                    continue;
+                }
+                buf[(ny * w) as usize + nx as usize] += err * weight / kernel.divisor;
+            }
+        }
+    }
+}
+
+/// Threshold each pixel against a tiled `size` x `size` map containing rank 0..size^2,
+/// shifted so it's centered on `threshold`
+fn ordered(
+    image: &mut GrayImage,
+    size: u32,
+    rank: impl Fn(u32, u32) -> u32,
+    threshold: &Threshold,
+) {
+    let levels = (size * size) as f32;
+    for (x, y, px) in image.enumerate_pixels_mut() {
+        let rank = rank(x % size, y % size);
+        let offset = ((rank as f32 + 0.5) / levels - 0.5) * 255.0;
+        let threshold = threshold.at(x, y) as f32 + offset;
+        px.0[0] = if (px.0[0] as f32) < threshold { 0 } else { 255 };
+    }
+}
+
+/// Value of (x, y) in bayer matrix of size `size` (must be power of 2)
+fn bayer(x: u32, y: u32, size: u32) -> u32 {
+    const BASE: [[u32; 2]; 2] = [[0, 2], [3, 1]];
+    if size == 1 {
+        return 0;
+    }
+    let half = size / 2;
+    4 * bayer(x % half, y % half, half) + BASE[(y / half) as usize][(x / half) as usize]
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+static BLUE_NOISE: LazyLock<Vec<u16>> = LazyLock::new(|| void_and_cluster(BLUE_NOISE_SIZE));
+
+/// Generate blue noise threshold map (rank 0..size^2) with void-and-cluster method
+fn void_and_cluster(size: usize) -> Vec<u16> {
+    const SIGMA: f32 = 1.5;
+    let n = size * size;
+
+    // gaussian weight by (wrapped around) distance between pixel
+    let gaussian = (0..n)
+        .map(|i| {
+            let (dx, dy) = (i % size, i / size);
+            let (dx, dy) = (dx.min(size - dx), dy.min(size - dy));
+            (-((dx * dx + dy * dy) as f32) / (2.0 * SIGMA * SIGMA)).exp()
+        })
+        .collect::<Vec<_>>();
+
+    struct Pattern<'a> {
+        size: usize,
+        gaussian: &'a [f32],
+        is_set: Vec<bool>,
+        energy: Vec<f32>,
+    }
+
+    impl Pattern<'_> {
+        fn set(&mut self, i: usize, value: bool) {
+            self.is_set[i] = value;
+            let sign = if value { 1.0 } else { -1.0 };
+            let (x0, y0) = (i % self.size, i / self.size);
+            for (j, energy) in self.energy.iter_mut().enumerate() {
+                let dx = (j % self.size + self.size - x0) % self.size;
+                let dy = (j / self.size + self.size - y0) % self.size;
+                *energy += sign * self.gaussian[dy * self.size + dx];
+            }
+        }
+
+        fn tightest_cluster(&self) -> usize {
+            (0..self.is_set.len())
+                .filter(|&i| self.is_set[i])
+                .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+                .expect("pattern to have set pixel")
+        }
+
+        fn largest_void(&self) -> usize {
+            (0..self.is_set.len())
+                .filter(|&i| !self.is_set[i])
+                .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+                .expect("pattern to have unset pixel")
+        }
+    }
+
+    let mut pattern = Pattern {
+        size,
+        gaussian: &gaussian,
+        is_set: vec![false; n],
+        energy: vec![0.0; n],
+    };
+
+    // initial pattern, fixed xorshift seed so the map is the same on every run
+    let initial = n / 10;
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut placed = 0;
+    while placed < initial {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let i = (state % n as u64) as usize;
+        if !pattern.is_set[i] {
+            pattern.set(i, true);
+            placed += 1;
+        }
+    }
+
+    // spread out initial pattern by moving tightest cluster into largest void
+    for _ in 0..n {
+        let cluster = pattern.tightest_cluster();
+        pattern.set(cluster, false);
+        let void = pattern.largest_void();
+        pattern.set(void, true);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; n];
+
+    // rank initial pixels by removing tightest cluster one by one
+    let initial_set = pattern.is_set.clone();
+    let initial_energy = pattern.energy.clone();
+    for r in (0..initial).rev() {
+        let cluster = pattern.tightest_cluster();
+        pattern.set(cluster, false);
+        rank[cluster] = r as u16;
+    }
+
+    // rank the rest by filling largest void one by one
+    pattern.is_set = initial_set;
+    pattern.energy = initial_energy;
+    for r in initial..n {
+        let void = pattern.largest_void();
+        pattern.set(void, true);
+        rank[void] = r as u16;
+    }
+
+    rank
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::braille::{self, BrailleConfig};
+    use crate::choice::Choice;
+    use crate::golden;
+
+    const ALL_MODES: [DitherMode; 9] = [
+        DitherMode::None,
+        DitherMode::FloydSteinberg,
+        DitherMode::Atkinson,
+        DitherMode::JarvisJudiceNinke,
+        DitherMode::Stucki,
+        DitherMode::Bayer2,
+        DitherMode::Bayer4,
+        DitherMode::Bayer8,
+        DitherMode::BlueNoise,
+    ];
+
+    #[test]
+    fn bayer_matrix() {
+        let m2 = (0..2)
+            .map(|y| (0..2).map(|x| bayer(x, y, 2)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(m2, [[0, 2], [3, 1]]);
+
+        let m4 = (0..4)
+            .map(|y| (0..4).map(|x| bayer(x, y, 4)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            m4,
+            [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]]
+        );
+    }
+
+    #[test]
+    fn blue_noise_is_permutation() {
+        let mut ranks = BLUE_NOISE.clone();
+        ranks.sort_unstable();
+        assert!(ranks
+            .iter()
+            .enumerate()
+            .all(|(i, &rank)| i == rank as usize));
+    }
+
+    #[test]
+    fn output_is_bilevel() {
+        let gradient = GrayImage::from_fn(64, 64, |x, y| Luma([(x * 2 + y * 2) as u8]));
+        for mode in ALL_MODES {
+            let mut image = gradient.clone();
+            dither(&mut image, mode, &Threshold::Global(125));
+            assert!(
+                image.pixels().all(|px| px.0[0] == 0 || px.0[0] == 255),
+                "{mode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn preserve_average_brightness() {
+        for mode in ALL_MODES.into_iter().filter(|&m| m != DitherMode::None) {
+            let mut image = GrayImage::from_pixel(64, 64, Luma([128]));
+            dither(&mut image, mode, &Threshold::Global(128));
+            let white = image.pixels().filter(|px| px.0[0] == 255).count();
+            let ratio = white as f32 / (64 * 64) as f32;
+            assert!((0.4..0.6).contains(&ratio), "{mode:?}: {ratio}");
+        }
+    }
+
+    #[test]
+    fn ordered_follow_threshold() {
+        for mode in [DitherMode::Bayer4, DitherMode::BlueNoise] {
+            let white = |threshold| {
+                let mut image = GrayImage::from_pixel(64, 64, Luma([128]));
+                dither(&mut image, mode, &threshold);
+                image.pixels().filter(|px| px.0[0] == 255).count()
+            };
+            assert!(white(Threshold::Global(64)) > white(Threshold::Global(128)));
+            assert!(white(Threshold::Global(128)) > white(Threshold::Global(192)));
+            // higher threshold on the right, so it comes out darker
+            let local = GrayImage::from_fn(64, 64, |x, _| Luma([if x < 32 { 64 } else { 192 }]));
+            let mut image = GrayImage::from_pixel(64, 64, Luma([128]));
+            dither(&mut image, mode, &Threshold::Local(local));
+            let white = |right: bool| {
+                image
+                    .enumerate_pixels()
+                    .filter(|&(x, _, px)| (x >= 32) == right && px.0[0] == 255)
+                    .count()
+            };
+            assert!(white(false) > white(true), "{mode:?}");
+        }
+    }
+
+    fn render(path: &str, width: u32, mode: DitherMode) -> String {
+        let image = image::open(path).unwrap();
+        let (w, h) = (image.width(), image.height());
+        let (w2, h2) = (2 * width, (h * 2 * width) / w);
+        let mut image = image
+            .resize_exact(w2, h2, image::imageops::FilterType::CatmullRom)
+            .to_luma8();
+
+        let config = BrailleConfig {
+            dither: mode,
+            ..Default::default()
+        };
+        let threshold = braille::compute_threshold(&image, &config);
+        dither(&mut image, config.dither, &threshold);
+
+        let mut text = String::new();
+        braille::image_to_patterns(&image, &config).for_each(|row| {
+            text.extend(row);
+            text.push('\n');
+        });
+        text
+    }
+
+    #[test]
+    fn golden() {
+        for mode in ALL_MODES {
+            golden::assert_golden(
+                &format!("dither/{}.txt", mode.name()),
+                &render("./sample_face.png", 40, mode),
+            );
+        }
+    }
+}
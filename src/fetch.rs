@@ -0,0 +1,308 @@
+use poise::serenity_prelude::{self as serenity, Message, UserId};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{redirect, Url};
+use std::net::IpAddr;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use thiserror::Error;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REDIRECTS: usize = 5;
+
+// user given urls must not reach the bot's host, its network or cloud metadata
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .dns_resolver(Arc::new(PublicResolver))
+        // a proxy would resolve the host itself
+        .no_proxy()
+        .redirect(redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if !allowed_url(attempt.url()) {
+                attempt.error(Error::NotPublic)
+            } else {
+                attempt.follow()
+            }
+        }))
+        .build()
+        .expect("valid client config")
+});
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Image is too large (max {max} bytes)")]
+    TooLarge { max: usize },
+    #[error("Not an image (content type `{0}`)")]
+    NotImage(String),
+    #[error("Image must be on a public address")]
+    NotPublic,
+    #[error("Failed to download image: {0}")]
+    Request(#[from] reqwest::Error),
+}
+
+/// Where to get an image from, as written in a message
+#[derive(Debug, PartialEq)]
+pub enum Source {
+    Url(String),
+    /// avatar of the user
+    User(UserId),
+}
+
+/// Parse an image url, custom emoji or user mention
+pub fn parse_source(text: &str) -> Option<Source> {
+    let text = text.trim();
+    if let Some(emoji) = serenity::parse_emoji(text) {
+        return Some(Source::Url(emoji.url()));
+    }
+    if let Some(user) = serenity::parse_user_mention(text) {
+        return Some(Source::User(user));
+    }
+
+    // <url> suppress embed in discord
+    let url = text
+        .strip_prefix('<')
+        .and_then(|url| url.strip_suffix('>'))
+        .unwrap_or(text);
+    (url.starts_with("https://") || url.starts_with("http://")).then(|| Source::Url(url.to_owned()))
+}
+
+/// Url of the first image attached or embedded in `message`
+pub fn message_image(message: &Message) -> Option<String> {
+    let attachment = message.attachments.iter().find(|attachment| {
+        attachment.dimensions().is_some()
+            || attachment
+                .content_type
+                .as_deref()
+                .is_some_and(|ty| ty.starts_with("image/"))
+    });
+    if let Some(attachment) = attachment {
+        return Some(attachment.url.clone());
+    }
+
+    message.embeds.iter().find_map(|embed| {
+        let image = embed.image.as_ref().map(|image| &image.url);
+        let thumbnail = embed.thumbnail.as_ref().map(|thumbnail| &thumbnail.url);
+        image.or(thumbnail).cloned()
+    })
+}
+
+// on the internet, not of the host, a private network or link-local (e.g. metadata)
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // "this network", shared address space (carrier-grade NAT) and reserved
+                || a == 0
+                || (a == 100 && b & 0xc0 == 64)
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            let o = ip.octets();
+            let embedded = |at: usize| IpAddr::V4([o[at], o[at + 1], o[at + 2], o[at + 3]].into());
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            match ip.segments() {
+                // NAT64 and 6to4 reach the ipv4 address they embed
+                [0x64, 0xff9b, 0, 0, 0, 0, ..] => is_public(embedded(12)),
+                [0x2002, ..] => is_public(embedded(2)),
+                // ipv4-compatible (deprecated, with loopback and unspecified), local-use
+                // NAT64 and documentation
+                [0, 0, 0, 0, 0, 0, ..] | [0x64, 0xff9b, 1, ..] | [0x2001, 0xdb8, ..] => false,
+                // unique local and link-local
+                [first, ..] => {
+                    !(ip.is_multicast() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80)
+                }
+            }
+        }
+    }
+}
+
+// http(s) and not to a non-public ip, host names are checked when resolved
+fn allowed_url(url: &Url) -> bool {
+    let ip = url
+        .host_str()
+        .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+        .and_then(|host| host.parse().ok());
+    matches!(url.scheme(), "http" | "https") && ip.is_none_or(is_public)
+}
+
+// resolve only host names of public addresses
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .collect::<Vec<_>>();
+            // all of them, as any could be connected to
+            if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+                return Err(Error::NotPublic.into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// `error`, or `Error::NotPublic` if it's the cause
+fn request_error(error: reqwest::Error) -> Error {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&error);
+    while let Some(cause) = source {
+        if let Some(Error::NotPublic) = cause.downcast_ref() {
+            return Error::NotPublic;
+        }
+        source = cause.source();
+    }
+    error.into()
+}
+
+/// Download an image, rejecting non-image content, anything larger than `max_bytes`
+/// and urls that aren't on the internet
+pub async fn download(url: &str, max_bytes: usize) -> Result<Vec<u8>, Error> {
+    let request = CLIENT.get(url).build()?;
+    if !allowed_url(request.url()) {
+        return Err(Error::NotPublic);
+    }
+    let mut response = CLIENT
+        .execute(request)
+        .await
+        .map_err(request_error)?
+        .error_for_status()?;
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|ty| ty.to_str().ok())
+        .unwrap_or_default();
+    if !content_type.starts_with("image/") {
+        return Err(Error::NotImage(content_type.to_owned()));
+    }
+    if response
+        .content_length()
+        .is_some_and(|len| len > max_bytes as u64)
+    {
+        return Err(Error::TooLarge { max: max_bytes });
+    }
+
+    // content length can lie (or be missing), so check as we go
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if data.len() + chunk.len() > max_bytes {
+            return Err(Error::TooLarge { max: max_bytes });
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_url() {
+        assert_eq!(
+            parse_source("https://example.com/a.png"),
+            Some(Source::Url("https://example.com/a.png".to_owned()))
+        );
+        assert_eq!(
+            parse_source(" <http://example.com/a.gif> "),
+            Some(Source::Url("http://example.com/a.gif".to_owned()))
+        );
+        assert_eq!(parse_source("ftp://example.com/a.png"), None);
+        assert_eq!(parse_source("hello"), None);
+    }
+
+    #[test]
+    fn public_address() {
+        for ip in [
+            "1.1.1.1",
+            "162.159.128.233",
+            "2606:4700::1111",
+            "64:ff9b::1.1.1.1",
+            "2002:101:101::1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::10.0.0.1",
+            "64:ff9b:1::1.1.1.1",
+            "2002:a9fe:a9fe::1",
+            "2002:7f00:1::",
+            "::10.0.0.1",
+            "::1.1.1.1",
+            "2001:db8::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn reject_local_url() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        for url in [
+            format!("http://127.0.0.1:{port}/a.png"),
+            format!("http://2130706433:{port}/a.png"),
+            format!("http://localhost:{port}/a.png"),
+            "http://169.254.169.254/latest/meta-data/".to_owned(),
+            "http://[::ffff:127.0.0.1]/a.png".to_owned(),
+        ] {
+            let result = download(&url, 1 << 20).await;
+            assert!(matches!(result, Err(Error::NotPublic)), "{url}: {result:?}");
+        }
+        // not even connected to
+        assert!(listener.accept().is_err());
+    }
+
+    #[test]
+    fn parse_emoji() {
+        assert_eq!(
+            parse_source("<:blob:123456789012345678>"),
+            Some(Source::Url(
+                "https://cdn.discordapp.com/emojis/123456789012345678.png".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse_source("<a:party:123456789012345678>"),
+            Some(Source::Url(
+                "https://cdn.discordapp.com/emojis/123456789012345678.gif".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_user() {
+        assert_eq!(
+            parse_source("<@123456789012345678>"),
+            Some(Source::User(UserId::new(123456789012345678)))
+        );
+        assert_eq!(
+            parse_source("<@!123456789012345678>"),
+            Some(Source::User(UserId::new(123456789012345678)))
+        );
+    }
+}
@@ -0,0 +1,48 @@
+//! Snapshot testing against text committed in `./golden`
+//!
+//! Run tests with `GOLDEN_BLESS=1` to (re)write the expected text instead of comparing.
+use std::path::Path;
+
+/// Sample images bundled in the repo root, used as golden inputs
+pub const SAMPLES: [&str; 4] = [
+    "sample_face.png",
+    "sample_1.png",
+    "tux.png",
+    "uto_2_true.webp",
+];
+
+/// Name of a sample without extension, used in golden file name
+pub fn sample_name(sample: &str) -> &str {
+    sample.rsplit_once('.').map_or(sample, |(name, _)| name)
+}
+
+/// Compare `actual` to `./golden/{path}`, or overwrite it when blessing
+#[track_caller]
+pub fn assert_golden(path: &str, actual: &str) {
+    let path = Path::new("./golden").join(path);
+    if std::env::var_os("GOLDEN_BLESS").is_some_and(|bless| bless != "0") {
+        std::fs::create_dir_all(path.parent().expect("golden file in a directory")).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "cannot read {} ({e}), run with GOLDEN_BLESS=1 to create it",
+            path.display()
+        )
+    });
+    if actual != expected {
+        let line = actual
+            .lines()
+            .zip(expected.lines())
+            .position(|(a, e)| a != e)
+            .unwrap_or(actual.lines().count().min(expected.lines().count()));
+        panic!(
+            "output differ from {} at line {}, run with GOLDEN_BLESS=1 if the change is intended\n\
+             --- actual\n{actual}\n--- expected\n{expected}",
+            path.display(),
+            line + 1,
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! Uncontracted (grade 1) english braille, following unified english braille
+//!
+//! Unlike the rest of the crate, braille here is read as text rather than pixels.
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use crate::braille::dots_to_braille;
+
+// dots of a-z
+const LETTERS: [&str; 26] = [
+    "1", "12", "14", "145", "15", "124", "1245", "125", "24", "245", // a-j
+    "13", "123", "134", "1345", "135", "1234", "12345", "1235", "234", "2345", // k-t
+    "136", "1236", "2456", "1346", "13456", "1356", // u-z
+];
+// 1-9 and 0 reuse the dots of a-j
+const DIGITS: &str = "1234567890";
+const PUNCTUATION: [(char, &[&str]); 12] = [
+    (',', &["2"]),
+    (';', &["23"]),
+    (':', &["25"]),
+    ('.', &["256"]),
+    ('!', &["235"]),
+    ('?', &["236"]),
+    ('\'', &["3"]),
+    ('-', &["36"]),
+    ('(', &["5", "126"]),
+    (')', &["5", "345"]),
+    ('/', &["456", "34"]),
+    ('"', &["6", "2356"]),
+];
+const CAPITAL: &str = "6";
+const NUMERIC: &str = "3456";
+// end numeric mode when a-j follow a number
+const GRADE1: &str = "56";
+const SPACE: char = '⠀';
+
+struct Table {
+    letters: HashMap<char, char>,
+    digits: HashMap<char, char>,
+    punctuation: Vec<(char, Vec<char>)>,
+    capital: char,
+    numeric: char,
+    grade1: char,
+}
+
+static TABLE: LazyLock<Table> = LazyLock::new(|| Table {
+    letters: ('a'..='z').zip(LETTERS.map(cell)).collect(),
+    digits: DIGITS.chars().zip(LETTERS.map(cell)).collect(),
+    punctuation: PUNCTUATION
+        .iter()
+        .map(|(c, cells)| (*c, cells.iter().map(|dots| cell(dots)).collect()))
+        .collect(),
+    capital: cell(CAPITAL),
+    numeric: cell(NUMERIC),
+    grade1: cell(GRADE1),
+});
+
+impl Table {
+    fn punctuation(&self, c: char) -> Option<&[char]> {
+        let (_, cells) = self.punctuation.iter().find(|(p, _)| *p == c)?;
+        Some(cells)
+    }
+}
+
+// braille with dots written as a string of digits, e.g. "1245"
+fn cell(dots: &str) -> char {
+    let dots = dots.bytes().map(|b| b - b'0').collect::<Vec<_>>();
+    dots_to_braille(&dots)
+}
+
+/// Whether `text` (ignoring whitespace) is entirely braille
+pub fn is_braille(text: &str) -> bool {
+    let mut chars = text.chars().filter(|c| !c.is_whitespace()).peekable();
+    chars.peek().is_some() && chars.all(|c| ('\u{2800}'..='\u{28FF}').contains(&c))
+}
+
+/// Transliterate text into grade 1 braille, character without braille is kept as is
+pub fn encode(text: &str) -> String {
+    let table = &*TABLE;
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut out = String::new();
+    let mut numeric = false;
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next_is_digit = chars.get(i + 1).is_some_and(char::is_ascii_digit);
+
+        if let Some(&digit) = table.digits.get(&c) {
+            if !numeric {
+                out.push(table.numeric);
+                numeric = true;
+            }
+            out.push(digit);
+        } else if numeric && matches!(c, '.' | ',') && next_is_digit {
+            // decimal point or thousand separator, still the same number
+            out.extend(table.punctuation(c).unwrap_or_default());
+        } else if c.is_ascii_alphabetic() {
+            let len = chars[i..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphabetic())
+                .count();
+            let word = &chars[i..i + len];
+            if numeric && ('a'..='j').contains(&word[0]) {
+                out.push(table.grade1);
+            }
+            numeric = false;
+
+            let all_caps = len >= 2 && word.iter().all(char::is_ascii_uppercase);
+            if all_caps {
+                out.extend([table.capital, table.capital]);
+            }
+            for c in word {
+                if c.is_ascii_uppercase() && !all_caps {
+                    out.push(table.capital);
+                }
+                out.push(table.letters[&c.to_ascii_lowercase()]);
+            }
+            i += len;
+            continue;
+        } else {
+            numeric = false;
+            match table.punctuation(c) {
+                Some(cells) => out.extend(cells),
+                None if c == ' ' => out.push(SPACE),
+                None => out.push(c),
+            }
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Transliterate grade 1 braille back into text, non-braille character is kept as is
+pub fn decode(braille: &str) -> String {
+    #[derive(PartialEq)]
+    enum Caps {
+        None,
+        Letter,
+        Word,
+    }
+
+    let table = &*TABLE;
+    let find = |map: &HashMap<char, char>, cell: char| {
+        map.iter().find(|(_, &v)| v == cell).map(|(&k, _)| k)
+    };
+    let cells = braille.chars().collect::<Vec<_>>();
+    let mut out = String::new();
+    let mut numeric = false;
+    let mut caps = Caps::None;
+
+    let mut i = 0;
+    while i < cells.len() {
+        let c = cells[i];
+        let next_is_digit = cells
+            .get(i + 1)
+            .is_some_and(|&next| find(&table.digits, next).is_some());
+
+        if numeric {
+            if let Some(digit) = find(&table.digits, c) {
+                out.push(digit);
+                i += 1;
+                continue;
+            }
+            let separator = ['.', ',']
+                .into_iter()
+                .find(|&p| table.punctuation(p) == Some(&[c][..]));
+            if let (true, Some(separator)) = (next_is_digit, separator) {
+                out.push(separator);
+                i += 1;
+                continue;
+            }
+            numeric = false;
+        }
+
+        if c == table.numeric {
+            numeric = true;
+            caps = Caps::None;
+            i += 1;
+            continue;
+        }
+        // multi-cell punctuation first, `"` start like a capital
+        if let Some((p, len)) = table
+            .punctuation
+            .iter()
+            .find(|(_, p)| cells[i..].starts_with(p))
+            .map(|(p, cells)| (*p, cells.len()))
+        {
+            out.push(p);
+            caps = Caps::None;
+            i += len;
+            continue;
+        }
+        if c == table.capital {
+            if cells.get(i + 1) == Some(&table.capital) {
+                caps = Caps::Word;
+                i += 2;
+            } else {
+                caps = Caps::Letter;
+                i += 1;
+            }
+            continue;
+        }
+        if let Some(letter) = find(&table.letters, c) {
+            if caps == Caps::None {
+                out.push(letter);
+            } else {
+                out.push(letter.to_ascii_uppercase());
+            }
+            if caps == Caps::Letter {
+                caps = Caps::None;
+            }
+            i += 1;
+            continue;
+        }
+
+        caps = Caps::None;
+        match c {
+            // only mean anything after a number
+            c if c == table.grade1 => {}
+            SPACE => out.push(' '),
+            c => out.push(c),
+        }
+        i += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_letters() {
+        assert_eq!(encode("hello world"), "⠓⠑⠇⠇⠕⠀⠺⠕⠗⠇⠙");
+        assert_eq!(
+            encode("abcdefghijklmnopqrstuvwxyz"),
+            "⠁⠃⠉⠙⠑⠋⠛⠓⠊⠚⠅⠇⠍⠝⠕⠏⠟⠗⠎⠞⠥⠧⠺⠭⠽⠵"
+        );
+    }
+
+    #[test]
+    fn capital() {
+        assert_eq!(encode("Hi"), "⠠⠓⠊");
+        assert_eq!(encode("NASA"), "⠠⠠⠝⠁⠎⠁");
+        assert_eq!(encode("McD"), "⠠⠍⠉⠠⠙");
+        assert_eq!(encode("I"), "⠠⠊");
+    }
+
+    #[test]
+    fn number() {
+        assert_eq!(encode("2024"), "⠼⠃⠚⠃⠙");
+        assert_eq!(encode("3.5"), "⠼⠉⠲⠑");
+        assert_eq!(encode("1,000."), "⠼⠁⠂⠚⠚⠚⠲");
+        // a-j would be read as digit
+        assert_eq!(encode("2b"), "⠼⠃⠰⠃");
+        assert_eq!(encode("2x"), "⠼⠃⠭");
+        assert_eq!(encode("2 b"), "⠼⠃⠀⠃");
+    }
+
+    #[test]
+    fn punctuation() {
+        assert_eq!(encode("wait, what?!"), "⠺⠁⠊⠞⠂⠀⠺⠓⠁⠞⠦⠖");
+        assert_eq!(encode("(a/b)"), "⠐⠣⠁⠸⠌⠃⠐⠜");
+        assert_eq!(encode("\"A\""), "⠠⠶⠠⠁⠠⠶");
+    }
+
+    #[test]
+    fn unknown_kept() {
+        assert_eq!(encode("a+é"), "⠁+é");
+        assert_eq!(decode("⠁+é"), "a+é");
+    }
+
+    #[test]
+    fn roundtrip() {
+        for text in [
+            "Hello, World!",
+            "In 2024 NASA spent 3.5x (or 1,000.5?) more; it's \"fine\" - McDonald/AB",
+            "2b or not 2b\nthat is the question",
+            "room 101A and 7j",
+            "",
+        ] {
+            assert_eq!(decode(&encode(text)), text);
+        }
+    }
+
+    #[test]
+    fn detect_braille() {
+        assert!(is_braille("⠓⠊ ⠁"));
+        assert!(!is_braille("⠓⠊ a"));
+        assert!(!is_braille("  "));
+    }
+}
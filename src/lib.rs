@@ -0,0 +1,12 @@
+//! Image to text rendering, shared by the bot and the `disbot-braille` cli
+pub mod animation;
+pub mod ansi;
+pub mod braille;
+pub mod choice;
+pub mod decode;
+pub mod dither;
+#[cfg(test)]
+mod golden;
+pub mod grade1;
+pub mod preprocess;
+pub mod render;
@@ -1,8 +1,15 @@
 #![deny(unused_must_use)]
-mod braille;
-mod pyremote;
-
+mod fetch;
+mod plot;
+mod policy;
+mod pool;
+mod runner;
+mod sandbox;
+mod session;
+
+mod banner;
 mod fibo;
+mod paginator;
 mod unicode;
 
 use poise::command;
@@ -15,17 +22,29 @@ use std::time::Duration;
 use tokio::time::MissedTickBehavior;
 
 use color_eyre::Result;
+use disbot_v2::{decode, grade1};
 use rand::prelude::*;
 use std::env;
+use std::io;
 
 const DISCORD_MESSAGE_LIMIT: usize = 2000;
 const DISCORD_WIDTH_LIMIT: usize = 60;
+const MAX_INPUT_SIZE: usize = 8 << 20;
+// snippets (of any language) running at once, and waiting for their turn
+const PY_WORKERS: usize = 4;
+const PY_QUEUE: usize = 16;
+const SESSION_IDLE: Duration = Duration::from_secs(10 * 60);
+const MAX_SESSIONS: usize = 8;
 
 thread_local! {
     static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_os_rng());
 }
 
-struct Data {}
+struct Data {
+    image_limits: decode::Limits,
+    pool: pool::Pool,
+    sessions: session::Sessions,
+}
 
 type Error = color_eyre::eyre::Error;
 type Context<'a> = poise::Context<'a, Data, Error>;
@@ -35,6 +54,10 @@ async fn main() -> Result<()> {
     dotenvy::dotenv().ok();
     color_eyre::install()?;
 
+    // before any python runs
+    let policy_path = env::var("SANDBOX_POLICY").unwrap_or_else(|_| policy::POLICY.to_owned());
+    policy::set(policy::Policy::load(policy_path)?);
+
     // Login with a bot token from the environment
     let token = env::var("DISCORD_TOKEN").expect("DISCORD_TOKEN envar should be set");
     let intents = GatewayIntents::non_privileged();
@@ -44,10 +67,14 @@ async fn main() -> Result<()> {
             commands: vec![
                 hello(),
                 count(),
+                banner::banner(),
+                braille(),
                 fibo::fibo(),
                 py(),
                 repeat(),
+                run(),
                 unicode::unicode(),
+                unicode::from_braille(),
             ],
             prefix_options: poise::PrefixFrameworkOptions {
                 prefix: Some("~".to_owned()),
@@ -58,7 +85,11 @@ async fn main() -> Result<()> {
         .setup(|ctx, _ready, framework| {
             Box::pin(async move {
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                Ok(Data {})
+                Ok(Data {
+                    image_limits: decode::Limits::from_env(),
+                    pool: pool::Pool::new(PY_WORKERS, PY_QUEUE),
+                    sessions: session::Sessions::new(SESSION_IDLE, MAX_SESSIONS),
+                })
             })
         })
         .build();
@@ -143,10 +174,149 @@ async fn count(ctx: Context<'_>, second: u8, weeb: Option<bool>) -> Result<()> {
 
 /// Run python code
 ///
-/// usage: |py ```python_code```|
-#[command(prefix_command)]
+/// usage: |py ```python_code``` ```stdin```|
+///
+/// An attachment named `stdin.txt` is also read as stdin,
+/// other attachments are readable (but not writable) in the working directory.
+/// In a session (|py session start|), variables are kept from one run to the next.
+/// To draw with matplotlib or PIL, use |py plot| instead.
+#[command(prefix_command, subcommands("session", "py_plot"))]
 async fn py(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
-    async fn send_as_attachment(ctx: Context<'_>, stdout: Vec<u8>, stderr: Vec<u8>) -> Result<()> {
+    run_code(ctx, &runner::PYTHON, runner::code_blocks(&code)).await
+}
+
+/// Run python code that can use matplotlib and PIL, images are sent back
+///
+/// usage: |py plot ```python_code``` ```stdin```|
+///
+/// Figures still open at the end are sent, as well as those and PIL images
+/// passed to `show()` along the way (up to 4, of 2 MiB each).
+#[command(prefix_command, rename = "plot")]
+async fn py_plot(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
+    run_code(ctx, &runner::PYTHON_PLOT, runner::code_blocks(&code)).await
+}
+
+/// Python session of your own, keeping variables between |py| runs
+#[command(
+    prefix_command,
+    subcommands("session_start", "session_stop", "session_reset"),
+    subcommand_required
+)]
+async fn session(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Start your session, attachments are its data files
+#[command(prefix_command, rename = "start")]
+async fn session_start(ctx: Context<'_>) -> Result<()> {
+    let Some(files) = attachments(ctx).await? else {
+        return Ok(());
+    };
+    ctx.defer_or_broadcast().await?;
+    let reply = match ctx.data().sessions.start(ctx.author().id, files).await {
+        Ok(()) => format!(
+            "Session started, it ends after {} minutes without code",
+            SESSION_IDLE.as_secs() / 60
+        ),
+        Err(session::Error::Run(runner::Error::IO(e)))
+            if e.kind() != io::ErrorKind::InvalidInput =>
+        {
+            return Err(e.into());
+        }
+        Err(session::Error::Run(e @ runner::Error::Prelude { .. })) => return Err(e.into()),
+        Err(e) => e.to_string(),
+    };
+    ctx.reply(reply).await?;
+    Ok(())
+}
+
+/// Stop your session
+#[command(prefix_command, rename = "stop")]
+async fn session_stop(ctx: Context<'_>) -> Result<()> {
+    let reply = match ctx.data().sessions.stop(ctx.author().id).await {
+        Ok(()) => "Session stopped".to_owned(),
+        Err(session::Error::Run(e)) => return Err(e.into()),
+        Err(e) => e.to_string(),
+    };
+    ctx.reply(reply).await?;
+    Ok(())
+}
+
+/// Start your session over, with the same data files
+#[command(prefix_command, rename = "reset")]
+async fn session_reset(ctx: Context<'_>) -> Result<()> {
+    let reply = match ctx.data().sessions.reset(ctx.author().id).await {
+        Ok(()) => "Session reset".to_owned(),
+        Err(session::Error::Run(runner::Error::IO(e))) => return Err(e.into()),
+        Err(session::Error::Run(e @ runner::Error::Prelude { .. })) => return Err(e.into()),
+        Err(e) => e.to_string(),
+    };
+    ctx.reply(reply).await?;
+    Ok(())
+}
+
+/// Run code in the language of its code block
+///
+/// usage: |run ```language
+/// code``` ```stdin```|
+///
+/// Language is one of py, js, rust, lua or sh, input is the same as |py|.
+#[command(prefix_command)]
+async fn run(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
+    let blocks = runner::code_blocks(&code);
+    let tag = blocks
+        .first()
+        .and_then(|block| block.tag)
+        .unwrap_or_default();
+    let Some(language) = runner::find(tag) else {
+        let tags = runner::LANGUAGES
+            .map(|language| language.tags[0])
+            .join(", ");
+        let reply = if tag.is_empty() {
+            format!("Start the code block with its language, one of: {tags}")
+        } else {
+            format!("Unknown language `{tag}`, use one of: {tags}")
+        };
+        ctx.reply(reply).await?;
+        return Ok(());
+    };
+    run_code(ctx, language, blocks).await
+}
+
+// name and content of the message's attachments, `None` when too large (replied)
+async fn attachments(ctx: Context<'_>) -> Result<Option<Vec<(String, Vec<u8>)>>> {
+    let poise::Context::Prefix(prefix) = ctx else {
+        return Ok(Some(Vec::new()));
+    };
+    let attachments = &prefix.msg.attachments;
+    let size = attachments.iter().map(|a| a.size as usize).sum::<usize>();
+    if size > MAX_INPUT_SIZE {
+        ctx.reply(format!(
+            "Attachments must be at most {} MiB in total",
+            MAX_INPUT_SIZE >> 20
+        ))
+        .await?;
+        return Ok(None);
+    }
+    let mut files = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        files.push((attachment.filename.clone(), attachment.download().await?));
+    }
+    Ok(Some(files))
+}
+
+// first code block is the code, the second is stdin
+async fn run_code(
+    ctx: Context<'_>,
+    language: &'static runner::Language,
+    blocks: Vec<runner::CodeBlock<'_>>,
+) -> Result<()> {
+    async fn send_as_attachment(
+        ctx: Context<'_>,
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+        images: Vec<(String, Vec<u8>)>,
+    ) -> Result<()> {
         let mut reply = CreateReply::default().reply(true);
         if !stdout.is_empty() {
             reply = reply.attachment(CreateAttachment::bytes(stdout, "stdout.txt"));
@@ -154,28 +324,80 @@ async fn py(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
         if !stderr.is_empty() {
             reply = reply.attachment(CreateAttachment::bytes(stderr, "stderr.txt"));
         }
+        for (name, image) in images {
+            reply = reply.attachment(CreateAttachment::bytes(image, name));
+        }
 
         ctx.send(reply).await?;
         Ok(())
     }
 
-    let mut code = code.trim();
-    if code.starts_with("```") && code.ends_with("```") {
-        code = &code[3..code.len() - 3];
-    } else if code.starts_with('`') && code.ends_with('`') {
-        code = &code[1..code.len() - 1];
+    let mut blocks = blocks.into_iter().map(|block| block.code);
+    let mut input = runner::Input::from(blocks.next().unwrap_or_default());
+    input.stdin = blocks.next().unwrap_or_default().as_bytes().to_vec();
+
+    let Some(files) = attachments(ctx).await? else {
+        return Ok(());
+    };
+    for (name, content) in files {
+        if name == "stdin.txt" {
+            input.stdin = content;
+        } else {
+            input.files.push((name, content));
+        }
     }
     ctx.defer_or_broadcast().await?;
 
-    // run python code
-    let output = match pyremote::secure_run_python_code(code, Duration::from_secs(5)).await {
+    let timeout = if language.policy {
+        policy::get().timeout
+    } else {
+        Duration::from_secs(5)
+    };
+    let mut images = Vec::new();
+    let result = if std::ptr::eq(language, &runner::PYTHON) {
+        let data = ctx.data();
+        match data.sessions.run(ctx.author().id, input, timeout).await {
+            Ok(result) => result,
+            Err(input) => data.pool.run(input, timeout).await,
+        }
+    } else if std::ptr::eq(language, &runner::PYTHON_PLOT) {
+        let run = plot::run(input, timeout);
+        ctx.data().pool.limit(run).await.map(|plot| {
+            images = plot.images;
+            plot.output
+        })
+    } else {
+        ctx.data()
+            .pool
+            .limit(runner::run(language, input, timeout))
+            .await
+    };
+    let output = match result {
         Ok(output) => output,
-        Err(pyremote::Error::Timeout { timeout }) => {
+        Err(runner::Error::Timeout { timeout }) => {
             ctx.reply(format!("Code Timeout in {} seconds", timeout.as_secs()))
                 .await?;
             return Ok(());
         }
-        Err(pyremote::Error::IO(e)) => {
+        // still worth showing what was printed
+        Err(runner::Error::OutputLimit { output, .. }) => output,
+        Err(e @ (runner::Error::MemoryLimit { .. } | runner::Error::Killed(_))) => {
+            ctx.reply(format!("Code {e}")).await?;
+            return Ok(());
+        }
+        Err(e @ (runner::Error::Unavailable { .. } | runner::Error::Busy)) => {
+            ctx.reply(e.to_string()).await?;
+            return Ok(());
+        }
+        // e.g. bad attachment name
+        Err(runner::Error::IO(e)) if e.kind() == io::ErrorKind::InvalidInput => {
+            ctx.reply(e.to_string()).await?;
+            return Ok(());
+        }
+        Err(runner::Error::IO(e)) => {
+            return Err(e.into());
+        }
+        Err(e @ runner::Error::Prelude { .. }) => {
             return Err(e.into());
         }
     };
@@ -186,7 +408,7 @@ async fn py(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
 
     let (Ok(stdout), Ok(stderr)) = (str::from_utf8(stdout), str::from_utf8(stderr)) else {
         // message content non-utf8 bytes, send as attachment
-        send_as_attachment(ctx, stdout.into(), stderr.into()).await?;
+        send_as_attachment(ctx, stdout.into(), stderr.into(), images).await?;
         return Ok(());
     };
 
@@ -199,15 +421,45 @@ async fn py(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
     );
 
     match report.chars().count() {
-        0 => {
+        0 if images.is_empty() => {
             ctx.reply("*<empty output>*").await?;
         }
-        1..=DISCORD_MESSAGE_LIMIT => {
-            ctx.reply(report).await?;
+        0..=DISCORD_MESSAGE_LIMIT => {
+            let mut reply = CreateReply::default().reply(true).content(report);
+            for (name, image) in images {
+                reply = reply.attachment(CreateAttachment::bytes(image, name));
+            }
+            ctx.send(reply).await?;
         }
         _ => {
             // message too large, send as attachment
-            send_as_attachment(ctx, stdout.into(), stderr.into()).await?;
+            send_as_attachment(ctx, stdout.into(), stderr.into(), images).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Transliterate english text into grade 1 braille, or braille back into text
+#[command(prefix_command, slash_command)]
+async fn braille(ctx: Context<'_>, #[rest] text: String) -> Result<()> {
+    let text = text.trim();
+    let result = if grade1::is_braille(text) {
+        grade1::decode(text)
+    } else {
+        grade1::encode(text)
+    };
+
+    match result.chars().count() {
+        0 => {
+            ctx.reply("Must have text to transliterate").await?;
+        }
+        1..=DISCORD_MESSAGE_LIMIT => {
+            ctx.reply(result).await?;
+        }
+        _ => {
+            let reply = CreateReply::default()
+                .attachment(CreateAttachment::bytes(result.into_bytes(), "braille.txt"));
+            ctx.send(reply).await?;
         }
     }
     Ok(())
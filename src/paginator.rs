@@ -0,0 +1,116 @@
+use std::iter::FusedIterator;
+
+/// Group lines into pages where each page (including newlines) is at most `limit` chars,
+/// line that is longer than a page by itself will be split across pages
+pub struct Paginator<I> {
+    lines: I,
+    pending: Option<String>,
+    limit: usize,
+}
+
+impl<I> Paginator<I>
+where
+    I: Iterator<Item = String>,
+{
+    pub fn new(lines: I, limit: usize) -> Self {
+        assert!(limit >= 2, "page must fit at least one char and a newline");
+        Paginator {
+            lines,
+            pending: None,
+            limit,
+        }
+    }
+}
+
+impl<I> Iterator for Paginator<I>
+where
+    I: Iterator<Item = String>,
+{
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut page = String::new();
+        let mut page_len = 0;
+
+        while let Some(line) = self.pending.take().or_else(|| self.lines.next()) {
+            let line_len = line.chars().count() + 1; // +1 from newline
+
+            if page_len + line_len <= self.limit {
+                page.push_str(&line);
+                page.push('\n');
+                page_len += line_len;
+            } else if page_len == 0 {
+                // line alone doesn't fit in a page, split it
+                let (split, _) = line
+                    .char_indices()
+                    .nth(self.limit - 1)
+                    .expect("line to be longer than limit");
+                page.push_str(&line[..split]);
+                page.push('\n');
+                self.pending = Some(line[split..].to_owned());
+                break;
+            } else {
+                // no room left, keep line for next page
+                self.pending = Some(line);
+                break;
+            }
+        }
+
+        (!page.is_empty()).then_some(page)
+    }
+}
+
+impl<I> FusedIterator for Paginator<I> where I: FusedIterator<Item = String> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paginate(lines: &[&str], limit: usize) -> Vec<String> {
+        Paginator::new(lines.iter().map(|s| s.to_string()), limit).collect()
+    }
+
+    #[test]
+    fn empty() {
+        assert!(paginate(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn fit_in_one_page() {
+        assert_eq!(paginate(&["ab", "cd"], 6), vec!["ab\ncd\n"]);
+    }
+
+    #[test]
+    fn split_between_lines() {
+        assert_eq!(
+            paginate(&["ab", "cd", "ef"], 5),
+            vec!["ab\n", "cd\n", "ef\n"]
+        );
+        assert_eq!(paginate(&["ab", "cd", "ef"], 6), vec!["ab\ncd\n", "ef\n"]);
+    }
+
+    #[test]
+    fn count_chars_not_bytes() {
+        assert_eq!(paginate(&["⣿⣿", "⣿⣿"], 6), vec!["⣿⣿\n⣿⣿\n"]);
+    }
+
+    #[test]
+    fn split_long_line() {
+        assert_eq!(
+            paginate(&["⣿⣿⣿⣿⣿", "a"], 3),
+            vec!["⣿⣿\n", "⣿⣿\n", "⣿\n", "a\n"]
+        );
+    }
+
+    #[test]
+    fn respect_limit() {
+        let lines = (0..100).map(|i| "⠄".repeat(i % 37)).collect::<Vec<_>>();
+        let lines = lines.iter().map(String::as_str).collect::<Vec<_>>();
+        let pages = paginate(&lines, 50);
+        assert!(pages.iter().all(|page| page.chars().count() <= 50));
+        assert_eq!(
+            pages.concat().replace('\n', ""),
+            lines.concat().replace('\n', "")
+        );
+    }
+}
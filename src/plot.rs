@@ -0,0 +1,178 @@
+//! Python runs with matplotlib and PIL allowed, returning the images they show
+use std::process::Output;
+use std::time::Duration;
+
+use crate::runner::{self, Error, Input, OUTPUT_LIMIT, PYTHON_PLOT};
+
+pub const MAX_IMAGES: usize = 4;
+pub const MAX_IMAGE_SIZE: usize = 2 << 20;
+
+/// Output of a plot run
+#[derive(Debug)]
+pub struct Plot {
+    pub output: Output,
+    /// name and content of PNG images
+    pub images: Vec<(String, Vec<u8>)>,
+}
+
+/// Run `input` as python code that can plot, see `python_dir/plot.py`
+pub async fn run(input: Input, timeout: Duration) -> Result<Plot, Error> {
+    let marker = runner::marker();
+    let mut stdin = format!("{marker} {MAX_IMAGES} {MAX_IMAGE_SIZE}\n").into_bytes();
+    stdin.extend_from_slice(&input.stdin);
+    let input = Input { stdin, ..input };
+
+    match runner::run(&PYTHON_PLOT, input, timeout).await {
+        Ok(mut output) => {
+            let images = take_images(&mut output.stdout, marker.as_bytes());
+            // the limit is raised for the images only
+            if output.stdout.len() > OUTPUT_LIMIT {
+                runner::truncate(&mut output.stdout, OUTPUT_LIMIT);
+                return Err(Error::OutputLimit {
+                    limit: OUTPUT_LIMIT,
+                    output,
+                });
+            }
+            Ok(Plot { output, images })
+        }
+        Err(Error::OutputLimit { mut output, .. }) => {
+            take_images(&mut output.stdout, marker.as_bytes());
+            runner::truncate(&mut output.stdout, OUTPUT_LIMIT);
+            Err(Error::OutputLimit {
+                limit: OUTPUT_LIMIT,
+                output,
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// cut the images sent after `marker` off `stdout`, each is its size and name in a
+// line then its content
+fn take_images(stdout: &mut Vec<u8>, marker: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let line = [b"\n", marker, b"\n"].concat();
+    let Some(start) = stdout.windows(line.len()).position(|window| window == line) else {
+        return Vec::new();
+    };
+    let sent = stdout.split_off(start);
+    let mut rest = &sent[line.len()..];
+
+    let mut images = Vec::new();
+    while images.len() < MAX_IMAGES {
+        let Some(end) = rest.iter().position(|&b| b == b'\n') else {
+            break;
+        };
+        let header = String::from_utf8_lossy(&rest[..end]);
+        let Some((size, name)) = header.split_once(' ') else {
+            break;
+        };
+        let Ok(size) = size.parse::<usize>() else {
+            break;
+        };
+        let content = &rest[end + 1..];
+        if size > MAX_IMAGE_SIZE || size > content.len() {
+            break;
+        }
+        images.push((name.to_owned(), content[..size].to_vec()));
+        rest = &content[size..];
+    }
+    images
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::tests::ESCAPE;
+
+    // a 1x1 png
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x02\0\0\0\x90wS\xde\0\0\0\x0cIDATx\x9cc\xf8\xff\xff?\0\x05\xfe\x02\xfe\r\xefF\xb8\0\0\0\0IEND\xaeB`\x82";
+
+    #[test]
+    fn split_images() {
+        let mut stdout = b"text\n".to_vec();
+        stdout.extend_from_slice(b"\nabc\n");
+        stdout.extend_from_slice(format!("{} 1.png\n", PNG.len()).as_bytes());
+        stdout.extend_from_slice(PNG);
+        stdout.extend_from_slice(b"3 2.png\nxyz");
+        let images = take_images(&mut stdout, b"abc");
+        assert_eq!(stdout, b"text\n");
+        assert_eq!(
+            images,
+            [
+                ("1.png".to_owned(), PNG.to_vec()),
+                ("2.png".to_owned(), b"xyz".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_images() {
+        let mut stdout = b"text".to_vec();
+        assert!(take_images(&mut stdout, b"abc").is_empty());
+        assert_eq!(stdout, b"text");
+
+        // cut short, over the size limit, too many
+        let mut stdout = b"\nabc\n1 a.png\nx10 b.png\nxy".to_vec();
+        assert_eq!(take_images(&mut stdout, b"abc").len(), 1);
+        let mut stdout = format!("\nabc\n{} a.png\n", MAX_IMAGE_SIZE + 1).into_bytes();
+        stdout.resize(stdout.len() + MAX_IMAGE_SIZE + 1, 0);
+        assert!(take_images(&mut stdout, b"abc").is_empty());
+        let mut stdout = b"\nabc\n".to_vec();
+        for i in 0..MAX_IMAGES + 1 {
+            stdout.extend_from_slice(format!("1 {i}.png\nx").as_bytes());
+        }
+        assert_eq!(take_images(&mut stdout, b"abc").len(), MAX_IMAGES);
+    }
+
+    // write png into the output directory like `show()` does
+    #[tokio::test]
+    async fn output_directory() {
+        let code = format!(
+            "{ESCAPE}print(input())\n\
+             for name in ['2.png', '1.png', '10.png', 'note.txt']:\n    \
+             fd = os.open('out/' + name, os.O_WRONLY | os.O_CREAT); os.write(fd, {PNG:?}); os.close(fd)"
+        )
+        .replace("[137, ", "bytes([137, ")
+        .replace("130]", "130])");
+        let input = Input {
+            code,
+            stdin: b"hello\n".to_vec(),
+            ..Default::default()
+        };
+        let plot = run(input, Duration::from_secs(5)).await.unwrap();
+        let stderr = String::from_utf8_lossy(&plot.output.stderr);
+        assert_eq!(plot.output.stdout, b"hello\n", "{stderr}");
+        let names = plot.images.iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names, ["1.png", "2.png", "10.png"]);
+        assert!(plot.images.iter().all(|(_, image)| image == PNG));
+    }
+
+    #[tokio::test]
+    async fn spoofed_import() {
+        // plot.py trust the modules it loaded, the caller can't pretend to be one of them
+        let code = "for spoof in [\
+                    lambda: __import__('os', {'__name__': 'sys'}), \
+                    lambda: __import__('os', {'__name__': 'matplotlib'}), \
+                    lambda: exec('import os', {'__name__': 'sys'}), \
+                    lambda: exec('__name__ = \"sys\"; import os', {})]:\n    \
+                    try: spoof(); print('imported')\n    \
+                    except ImportError: print('blocked')";
+        let plot = run(code.into(), Duration::from_secs(10)).await.unwrap();
+        let stderr = String::from_utf8_lossy(&plot.output.stderr);
+        assert_eq!(plot.output.stdout, b"blocked\n".repeat(4), "{stderr}");
+    }
+
+    #[tokio::test]
+    async fn matplotlib_figure() {
+        let code = "import matplotlib.pyplot as plt\nplt.plot([1, 2, 3], [1, 4, 9])";
+        let plot = run(code.into(), Duration::from_secs(10)).await.unwrap();
+        let stderr = String::from_utf8_lossy(&plot.output.stderr);
+        if stderr.contains("No module named 'matplotlib'") {
+            // not installed on this host
+            return;
+        }
+        assert!(plot.output.status.success(), "{stderr}");
+        assert_eq!(plot.images.len(), 1);
+        assert!(plot.images[0].1.starts_with(b"\x89PNG"));
+    }
+}
@@ -0,0 +1,188 @@
+//! What python code may do in the sandbox, from a TOML file read at startup
+//!
+//! The prelude generated from it is run before `python_dir/header.py`, which
+//! applies it.
+use serde::Deserialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use thiserror::Error;
+
+pub const POLICY: &str = "./python_dir/policy.toml";
+
+static CURRENT: OnceLock<Policy> = OnceLock::new();
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("can't read sandbox policy {}", .path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("invalid sandbox policy {}", .path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("`{0}` is not a python name")]
+    Name(String),
+    #[error("timeout and memory limit must be more than 0")]
+    Zero,
+}
+
+/// Sandbox policy of python code
+#[derive(Debug, PartialEq)]
+pub struct Policy {
+    /// program run in the sandbox
+    pub interpreter: String,
+    pub timeout: Duration,
+    /// bytes of address space
+    pub memory_limit: u64,
+    pub allowed_modules: Vec<String>,
+    pub banned_builtins: Vec<String>,
+}
+
+// as written in the file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    interpreter: String,
+    // seconds
+    timeout: u64,
+    // MiB
+    memory_limit: u64,
+    allowed_modules: Vec<String>,
+    banned_builtins: Vec<String>,
+}
+
+impl Default for Policy {
+    /// The shipped [`POLICY`], built in
+    fn default() -> Self {
+        let file = toml::from_str(include_str!("../python_dir/policy.toml"))
+            .expect("shipped sandbox policy to parse");
+        Self::new(file).expect("shipped sandbox policy to be valid")
+    }
+}
+
+// they are written in the prelude as they are
+fn is_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Policy {
+    /// Read the policy file at `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| Error::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        let file = toml::from_str(&text).map_err(|source| Error::Parse {
+            path: path.to_owned(),
+            source,
+        })?;
+        Self::new(file)
+    }
+
+    fn new(file: File) -> Result<Self, Error> {
+        let mut names = file.allowed_modules.iter().chain(&file.banned_builtins);
+        if let Some(name) = names.find(|name| !is_name(name)) {
+            return Err(Error::Name(name.clone()));
+        }
+        if file.timeout == 0 || file.memory_limit == 0 {
+            return Err(Error::Zero);
+        }
+        Ok(Self {
+            interpreter: file.interpreter,
+            timeout: Duration::from_secs(file.timeout),
+            memory_limit: file.memory_limit << 20,
+            allowed_modules: file.allowed_modules,
+            banned_builtins: file.banned_builtins,
+        })
+    }
+
+    /// Python code setting `allowed_modules` and `banned_builtins` for `header.py`,
+    /// run first so other preludes can allow more
+    pub fn prelude(&self) -> String {
+        let quote =
+            |names: &[String]| itertools::join(names.iter().map(|name| format!("'{name}'")), ", ");
+        format!(
+            "# generated from the sandbox policy\n\
+             allowed_modules = {{{}}}\n\
+             banned_builtins = [{}]\n",
+            quote(&self.allowed_modules),
+            quote(&self.banned_builtins),
+        )
+    }
+}
+
+/// Use `policy` from now on, it can only be set once and before [`get`]
+pub fn set(policy: Policy) {
+    CURRENT
+        .set(policy)
+        .expect("sandbox policy is set once at startup");
+}
+
+/// Policy in use, the default one if none was [`set`]
+pub fn get() -> &'static Policy {
+    CURRENT.get_or_init(Policy::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn load_str(text: &str) -> Result<Policy, Error> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+        Policy::load(file.path())
+    }
+
+    #[test]
+    fn shipped_policy() {
+        let policy = Policy::default();
+        assert_eq!(Policy::load(POLICY).unwrap(), policy);
+        assert_eq!(policy.interpreter, "python3");
+        assert!(policy.banned_builtins.contains(&"open".to_owned()));
+    }
+
+    #[test]
+    fn load_policy() {
+        let policy = load_str(
+            "interpreter = '/opt/python/bin/python3'\ntimeout = 2\nmemory_limit = 128\n\
+             allowed_modules = ['math']\nbanned_builtins = ['open', 'input']",
+        )
+        .unwrap();
+        assert_eq!(policy.interpreter, "/opt/python/bin/python3");
+        assert_eq!(policy.timeout, Duration::from_secs(2));
+        assert_eq!(policy.memory_limit, 128 << 20);
+        assert_eq!(
+            policy.prelude(),
+            "# generated from the sandbox policy\n\
+             allowed_modules = {'math'}\n\
+             banned_builtins = ['open', 'input']\n"
+        );
+    }
+
+    #[test]
+    fn invalid_policy() {
+        let valid = "interpreter = 'python3'\ntimeout = 2\nmemory_limit = 128\n";
+        let result = load_str(&format!(
+            "{valid}allowed_modules = [\"os'}} or {{'\"]\nbanned_builtins = []"
+        ));
+        assert!(matches!(result, Err(Error::Name(_))), "{result:?}");
+        let result = load_str(&format!("{valid}allowed_modules = []"));
+        assert!(matches!(result, Err(Error::Parse { .. })), "{result:?}");
+        let result = load_str(&format!(
+            "{valid}allowed_modules = []\nbanned_builtins = []\nnetwork = true"
+        ));
+        assert!(matches!(result, Err(Error::Parse { .. })), "{result:?}");
+        let result = load_str(
+            "interpreter = 'python3'\ntimeout = 0\nmemory_limit = 128\n\
+             allowed_modules = []\nbanned_builtins = []",
+        );
+        assert!(matches!(result, Err(Error::Zero)), "{result:?}");
+        let result = Policy::load("./python_dir/missing.toml");
+        assert!(matches!(result, Err(Error::Read { .. })), "{result:?}");
+    }
+}
@@ -0,0 +1,221 @@
+//! Warm python workers that already ran the header (and its numpy and scipy
+//! import), each run one snippet and is then replaced
+use std::future::Future;
+use std::process::Output;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use crate::runner::{self, Error, Input, Process, PYTHON};
+
+const WORKER: &str = "./python_dir/worker.py";
+
+/// Python runner with at most a fixed number of snippets running at once
+pub struct Pool {
+    idle: Arc<Mutex<Idle>>,
+    workers: usize,
+    // running snippets
+    running: Semaphore,
+    // running and queued snippets
+    admitted: Semaphore,
+}
+
+#[derive(Default)]
+struct Idle {
+    // started workers waiting for a snippet
+    workers: Vec<Process>,
+    // workers being started
+    starting: usize,
+}
+
+fn spawn_worker() -> Result<Process, Error> {
+    let header = PYTHON.prelude()?;
+    let worker = std::fs::read(WORKER)?;
+    PYTHON.spawn(&worker, &[("header.py", &header)])
+}
+
+impl Pool {
+    /// Pool running up to `workers` snippets at once, with `queue` more waiting for
+    /// their turn
+    pub fn new(workers: usize, queue: usize) -> Self {
+        let pool = Pool {
+            idle: Arc::default(),
+            workers,
+            running: Semaphore::new(workers),
+            admitted: Semaphore::new(workers + queue),
+        };
+        pool.refill();
+        pool
+    }
+
+    // start workers in the background until `workers` are idle
+    fn refill(&self) {
+        let missing = {
+            let mut idle = self.idle.lock().expect("not poisoned");
+            let missing = self
+                .workers
+                .saturating_sub(idle.workers.len() + idle.starting);
+            idle.starting += missing;
+            missing
+        };
+        for _ in 0..missing {
+            let idle = self.idle.clone();
+            tokio::task::spawn_blocking(move || {
+                let worker = spawn_worker();
+                let mut idle = idle.lock().expect("not poisoned");
+                idle.starting -= 1;
+                match worker {
+                    Ok(worker) => idle.workers.push(worker),
+                    // `run` start one itself then, and report it again
+                    Err(e) => eprintln!("Failed to start python worker: {e:?}"),
+                }
+            });
+        }
+    }
+
+    /// Run `input` as python code like [`runner::run`], or [`Error::Busy`] if the
+    /// queue is full
+    pub async fn run(&self, input: Input, timeout: Duration) -> Result<Output, Error> {
+        self.limit(self.run_worker(input, timeout)).await
+    }
+
+    /// Run `run` (e.g. code of another language) once it's its turn among the
+    /// pool's snippets, or [`Error::Busy`] if the queue is full
+    pub async fn limit<T>(&self, run: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        let Ok(_admitted) = self.admitted.try_acquire() else {
+            return Err(Error::Busy);
+        };
+        let _running = self.running.acquire().await.expect("never closed");
+        run.await
+    }
+
+    async fn run_worker(&self, input: Input, timeout: Duration) -> Result<Output, Error> {
+        // data files can only be given to a new process
+        if !input.files.is_empty() {
+            return runner::run(&PYTHON, input, timeout).await;
+        }
+        let worker = self.idle.lock().expect("not poisoned").workers.pop();
+        let worker = match worker {
+            Some(worker) => worker,
+            None => tokio::task::spawn_blocking(spawn_worker)
+                .await
+                .expect("not panicked")?,
+        };
+        self.refill();
+
+        let mut stdin = format!("{}\n", input.code.len()).into_bytes();
+        stdin.extend_from_slice(input.code.as_bytes());
+        stdin.extend_from_slice(&input.stdin);
+        worker.wait(stdin, timeout).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn run_in_worker() {
+        let pool = Pool::new(1, 0);
+        let input = Input {
+            code: "print(input(), 'ó')".to_owned(),
+            stdin: b"hi\n".to_vec(),
+            ..Default::default()
+        };
+        let output = pool.run(input, Duration::from_secs(5)).await.unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.stdout, "hi ó\n".as_bytes(), "{stderr}");
+    }
+
+    #[tokio::test]
+    async fn header_applied() {
+        let pool = Pool::new(1, 0);
+        let output = pool
+            .run("import os".into(), Duration::from_secs(5))
+            .await
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(stderr.contains("secure_importer"), "{stderr}");
+    }
+
+    #[tokio::test]
+    async fn traceback_of_code() {
+        let pool = Pool::new(1, 0);
+        let output = pool
+            .run("x = 1\nprint(1/0)".into(), Duration::from_secs(5))
+            .await
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("File \"main.py\", line 2"), "{stderr}");
+        assert!(stderr.contains("print(1/0)"), "{stderr}");
+        assert!(!stderr.contains("worker"), "{stderr}");
+        assert!(stderr
+            .trim_end()
+            .ends_with("ZeroDivisionError: division by zero"));
+    }
+
+    #[tokio::test]
+    async fn worker_recycled() {
+        let pool = Pool::new(1, 0);
+        for _ in 0..3 {
+            // state of a snippet doesn't leak to the next
+            let output = pool
+                .run(
+                    "print('x' in globals()); x = 1".into(),
+                    Duration::from_secs(5),
+                )
+                .await
+                .unwrap();
+            assert_eq!(output.stdout, b"False\n");
+        }
+        // replaced in the background
+        for _ in 0..50 {
+            if pool.idle.lock().unwrap().workers.len() == 1 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("not refilled");
+    }
+
+    #[tokio::test]
+    async fn data_files() {
+        let pool = Pool::new(1, 0);
+        // would clash with the worker's own files
+        let input = Input {
+            code: "print(1)".to_owned(),
+            files: vec![("header.py".to_owned(), b"broken(".to_vec())],
+            ..Default::default()
+        };
+        let output = pool.run(input, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(output.stdout, b"1\n");
+    }
+
+    #[tokio::test]
+    async fn queue_full() {
+        let pool = Pool::new(1, 1);
+        let sleep = || {
+            pool.run(
+                "import time; time.sleep(0.5)".into(),
+                Duration::from_secs(5),
+            )
+        };
+        let (first, second, third) = tokio::join!(sleep(), sleep(), sleep());
+        assert!(first.is_ok(), "{first:?}");
+        assert!(second.is_ok(), "{second:?}");
+        assert!(matches!(third, Err(Error::Busy)), "{third:?}");
+    }
+
+    #[tokio::test]
+    async fn memory_limit() {
+        let pool = Pool::new(1, 0);
+        let result = pool
+            .run("print(len('a' * 10**10))".into(), Duration::from_secs(5))
+            .await;
+        assert!(
+            matches!(result, Err(Error::MemoryLimit { .. })),
+            "{result:?}"
+        );
+    }
+}
@@ -0,0 +1,439 @@
+use image::imageops;
+use image::{DynamicImage, GrayImage, Luma};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ParseError {
+    #[error("unknown preprocess step `{0}`")]
+    UnknownStep(String),
+    #[error("invalid value `{value}` for preprocess step `{step}`")]
+    InvalidValue { step: String, value: String },
+    #[error("preprocess steps `crop`, `rotate` and `flip` must come before the others")]
+    GeometryAfterFilter,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeMode {
+    Sobel,
+    Canny,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    /// Add to every pixel, -255..=255
+    Brightness(i32),
+    /// Contrast change in percent, negative reduce contrast
+    Contrast(f32),
+    /// Above 1 brighten mid-tone, below 1 darken it
+    Gamma(f32),
+    /// Histogram equalization
+    Equalize,
+    /// Unsharp mask with gaussian blur of `sigma`
+    Sharpen(f32),
+    /// Replace image with its edges, drawn dark on bright background
+    Edge(EdgeMode),
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Clockwise rotation, multiple of 90 degree
+    Rotate(u32),
+    FlipHorizontal,
+    FlipVertical,
+}
+
+impl Step {
+    /// Geometric step is applied to the source image before resizing,
+    /// the rest is applied to the resized grayscale image.
+    fn is_geometric(&self) -> bool {
+        matches!(
+            self,
+            Step::Crop { .. } | Step::Rotate(_) | Step::FlipHorizontal | Step::FlipVertical
+        )
+    }
+}
+
+impl FromStr for Step {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (s.trim(), None),
+        };
+        let invalid = || ParseError::InvalidValue {
+            step: name.to_owned(),
+            value: value.unwrap_or_default().to_owned(),
+        };
+        let parse = |default: Option<&str>| {
+            value
+                .or(default)
+                .ok_or_else(invalid)?
+                .parse::<f32>()
+                .ok()
+                .filter(|v| v.is_finite())
+                .ok_or_else(invalid)
+        };
+
+        let step = match name {
+            "brightness" => Step::Brightness(parse(None)?.clamp(-255.0, 255.0) as i32),
+            "contrast" => Step::Contrast(parse(None)?),
+            "gamma" => {
+                let gamma = parse(None)?;
+                if gamma <= 0.0 {
+                    return Err(invalid());
+                }
+                Step::Gamma(gamma)
+            }
+            "equalize" => Step::Equalize,
+            "sharpen" => Step::Sharpen(parse(Some("1.0"))?.clamp(0.1, 10.0)),
+            "edge" => match value {
+                None | Some("sobel") => Step::Edge(EdgeMode::Sobel),
+                Some("canny") => Step::Edge(EdgeMode::Canny),
+                _ => return Err(invalid()),
+            },
+            "crop" => {
+                let values = value
+                    .ok_or_else(invalid)?
+                    .split(':')
+                    .map(|v| v.trim().parse::<u32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| invalid())?;
+                let &[x, y, width, height] = values.as_slice() else {
+                    return Err(invalid());
+                };
+                if width == 0 || height == 0 {
+                    return Err(invalid());
+                }
+                Step::Crop {
+                    x,
+                    y,
+                    width,
+                    height,
+                }
+            }
+            "rotate" => match value {
+                Some("90") => Step::Rotate(90),
+                Some("180") => Step::Rotate(180),
+                Some("270") => Step::Rotate(270),
+                _ => return Err(invalid()),
+            },
+            "flip" => match value {
+                None | Some("h") => Step::FlipHorizontal,
+                Some("v") => Step::FlipVertical,
+                _ => return Err(invalid()),
+            },
+            _ => return Err(ParseError::UnknownStep(name.to_owned())),
+        };
+        Ok(step)
+    }
+}
+
+/// Ordered list of image processing step,
+/// parsed from comma separated steps e.g. `crop=0:0:100:100,contrast=20,edge=canny`
+///
+/// Geometric steps (crop, rotate, flip) run on the source image and the others on
+/// the resized one, so parsing only accept geometric steps first, keeping the
+/// order they run in the same as written.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pipeline(pub Vec<Step>);
+
+impl FromStr for Pipeline {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let steps = s
+            .split(',')
+            .filter(|step| !step.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<Step>, _>>()?;
+        let filters = steps.iter().position(|step| !step.is_geometric());
+        if filters.is_some_and(|i| steps[i..].iter().any(Step::is_geometric)) {
+            return Err(ParseError::GeometryAfterFilter);
+        }
+        Ok(Pipeline(steps))
+    }
+}
+
+impl Pipeline {
+    /// Apply crop, rotate and flip in order, before any other step
+    pub fn apply_geometry(&self, mut image: DynamicImage) -> DynamicImage {
+        for step in self.0.iter().filter(|step| step.is_geometric()) {
+            image = match *step {
+                Step::Crop {
+                    x,
+                    y,
+                    width,
+                    height,
+                } => image.crop_imm(x, y, width, height),
+                Step::Rotate(90) => image.rotate90(),
+                Step::Rotate(180) => image.rotate180(),
+                Step::Rotate(270) => image.rotate270(),
+                Step::FlipHorizontal => image.fliph(),
+                Step::FlipVertical => image.flipv(),
+                _ => image,
+            };
+        }
+        image
+    }
+
+    /// Apply every non-geometric step in order, after the geometric ones
+    pub fn apply_filters(&self, mut image: GrayImage) -> GrayImage {
+        for step in self.0.iter().filter(|step| !step.is_geometric()) {
+            image = match *step {
+                Step::Brightness(value) => imageops::brighten(&image, value),
+                Step::Contrast(value) => imageops::contrast(&image, value),
+                Step::Gamma(gamma) => {
+                    let lut: [u8; 256] = std::array::from_fn(|v| {
+                        (255.0 * (v as f32 / 255.0).powf(1.0 / gamma)).round() as u8
+                    });
+                    map_luma(image, |v| lut[v as usize])
+                }
+                Step::Equalize => equalize(image),
+                Step::Sharpen(sigma) => imageops::unsharpen(&image, sigma, 0),
+                Step::Edge(EdgeMode::Sobel) => sobel(&image),
+                Step::Edge(EdgeMode::Canny) => canny(&image),
+                _ => image,
+            };
+        }
+        image
+    }
+}
+
+fn map_luma(mut image: GrayImage, f: impl Fn(u8) -> u8) -> GrayImage {
+    image.pixels_mut().for_each(|px| px.0[0] = f(px.0[0]));
+    image
+}
+
+fn equalize(image: GrayImage) -> GrayImage {
+    let mut hist = [0u64; 256];
+    image.pixels().for_each(|px| hist[px.0[0] as usize] += 1);
+
+    let mut cdf = [0u64; 256];
+    let mut acc = 0;
+    for (c, h) in cdf.iter_mut().zip(hist) {
+        acc += h;
+        *c = acc;
+    }
+
+    let total = acc;
+    let cdf_min = cdf.iter().copied().find(|&c| c > 0).unwrap_or(0);
+    if total == cdf_min {
+        // single color image, nothing to spread
+        return image;
+    }
+    map_luma(image, |v| {
+        ((cdf[v as usize] - cdf_min) * 255 / (total - cdf_min)) as u8
+    })
+}
+
+/// Horizontal & vertical sobel gradient of each pixel, edge are clamped
+fn sobel_gradient(image: &GrayImage) -> (Vec<f32>, Vec<f32>) {
+    let (w, h) = image.dimensions();
+    let px = |x: i64, y: i64| {
+        let x = x.clamp(0, w as i64 - 1) as u32;
+        let y = y.clamp(0, h as i64 - 1) as u32;
+        image.get_pixel(x, y).0[0] as f32
+    };
+
+    let mut gx = Vec::with_capacity((w * h) as usize);
+    let mut gy = Vec::with_capacity((w * h) as usize);
+    for y in 0..h as i64 {
+        for x in 0..w as i64 {
+            gx.push(
+                px(x + 1, y - 1) + 2.0 * px(x + 1, y) + px(x + 1, y + 1)
+                    - px(x - 1, y - 1)
+                    - 2.0 * px(x - 1, y)
+                    - px(x - 1, y + 1),
+            );
+            gy.push(
+                px(x - 1, y + 1) + 2.0 * px(x, y + 1) + px(x + 1, y + 1)
+                    - px(x - 1, y - 1)
+                    - 2.0 * px(x, y - 1)
+                    - px(x + 1, y - 1),
+            );
+        }
+    }
+    (gx, gy)
+}
+
+fn sobel(image: &GrayImage) -> GrayImage {
+    let (w, h) = image.dimensions();
+    let (gx, gy) = sobel_gradient(image);
+    let magnitude = gx
+        .iter()
+        .zip(&gy)
+        .map(|(x, y)| x.hypot(*y))
+        .collect::<Vec<_>>();
+
+    // normalize so the strongest edge is black
+    let max = magnitude.iter().copied().fold(0.0, f32::max).max(1.0);
+    GrayImage::from_fn(w, h, |x, y| {
+        let m = magnitude[(y * w + x) as usize];
+        Luma([255 - (m / max * 255.0) as u8])
+    })
+}
+
+fn canny(image: &GrayImage) -> GrayImage {
+    const SIGMA: f32 = 1.0;
+    // hysteresis threshold relative to the strongest gradient
+    const LOW: f32 = 0.1;
+    const HIGH: f32 = 0.25;
+
+    let (w, h) = image.dimensions();
+    let blurred = imageops::blur(image, SIGMA);
+    let (gx, gy) = sobel_gradient(&blurred);
+    let magnitude = gx
+        .iter()
+        .zip(&gy)
+        .map(|(x, y)| x.hypot(*y))
+        .collect::<Vec<_>>();
+    let at = |x: i64, y: i64| {
+        if x < 0 || y < 0 || x >= w as i64 || y >= h as i64 {
+            0.0
+        } else {
+            magnitude[(y * w as i64 + x) as usize]
+        }
+    };
+
+    // non-maximum suppression, keep only pixel that is the peak along gradient direction
+    let mut thin = vec![0.0; magnitude.len()];
+    for y in 0..h as i64 {
+        for x in 0..w as i64 {
+            let i = (y * w as i64 + x) as usize;
+            let angle = gy[i].atan2(gx[i]).to_degrees().rem_euclid(180.0);
+            let (dx, dy) = match angle {
+                a if !(22.5..157.5).contains(&a) => (1, 0),
+                a if a < 67.5 => (1, 1),
+                a if a < 112.5 => (0, 1),
+                _ => (-1, 1),
+            };
+            let m = magnitude[i];
+            if m >= at(x + dx, y + dy) && m >= at(x - dx, y - dy) {
+                thin[i] = m;
+            }
+        }
+    }
+
+    // hysteresis, grow strong edge into connected weak edge
+    let max = thin.iter().copied().fold(0.0, f32::max);
+    let (low, high) = (LOW * max, HIGH * max);
+    let mut is_edge = vec![false; thin.len()];
+    let mut stack = (0..thin.len())
+        .filter(|&i| max > 0.0 && thin[i] >= high)
+        .collect::<Vec<_>>();
+    stack.iter().for_each(|&i| is_edge[i] = true);
+    while let Some(i) = stack.pop() {
+        let (x, y) = ((i as u32 % w) as i64, (i as u32 / w) as i64);
+        for (dx, dy) in itertools::iproduct!(-1..=1, -1..=1) {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx >= w as i64 || ny >= h as i64 {
+                continue;
+            }
+            let j = (ny * w as i64 + nx) as usize;
+            if !is_edge[j] && thin[j] >= low {
+                is_edge[j] = true;
+                stack.push(j);
+            }
+        }
+    }
+
+    GrayImage::from_fn(w, h, |x, y| {
+        Luma([if is_edge[(y * w + x) as usize] {
+            0
+        } else {
+            255
+        }])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_in_order() {
+        let pipeline = "crop=1:2:3:4, flip=v,contrast=20,gamma=1.5,edge=canny"
+            .parse::<Pipeline>()
+            .unwrap();
+        assert_eq!(
+            pipeline.0,
+            [
+                Step::Crop {
+                    x: 1,
+                    y: 2,
+                    width: 3,
+                    height: 4
+                },
+                Step::FlipVertical,
+                Step::Contrast(20.0),
+                Step::Gamma(1.5),
+                Step::Edge(EdgeMode::Canny),
+            ]
+        );
+        assert_eq!(
+            "sharpen,sharpen=2,sharpen=50"
+                .parse::<Pipeline>()
+                .unwrap()
+                .0,
+            [Step::Sharpen(1.0), Step::Sharpen(2.0), Step::Sharpen(10.0)]
+        );
+        assert_eq!("".parse::<Pipeline>().unwrap(), Pipeline::default());
+    }
+
+    #[test]
+    fn parse_error() {
+        assert_eq!(
+            "blur".parse::<Pipeline>(),
+            Err(ParseError::UnknownStep("blur".to_owned()))
+        );
+        assert!("gamma=0".parse::<Pipeline>().is_err());
+        assert!("gamma=abc".parse::<Pipeline>().is_err());
+        assert!("rotate=45".parse::<Pipeline>().is_err());
+        assert!("crop=1:2:3".parse::<Pipeline>().is_err());
+        assert!("crop=0:0:0:10".parse::<Pipeline>().is_err());
+        assert_eq!(
+            "rotate=90,contrast=20,flip".parse::<Pipeline>(),
+            Err(ParseError::GeometryAfterFilter)
+        );
+    }
+
+    #[test]
+    fn geometry() {
+        let image = DynamicImage::new_luma8(40, 20);
+        let pipeline = "rotate=90,crop=0:0:10:30".parse::<Pipeline>().unwrap();
+        let image = pipeline.apply_geometry(image);
+        assert_eq!((image.width(), image.height()), (10, 30));
+    }
+
+    #[test]
+    fn gamma_keep_extremes() {
+        let image = GrayImage::from_fn(3, 1, |x, _| Luma([[0, 128, 255][x as usize]]));
+        let image = Pipeline(vec![Step::Gamma(2.0)]).apply_filters(image);
+        assert_eq!(image.get_pixel(0, 0).0[0], 0);
+        assert!(image.get_pixel(1, 0).0[0] > 128);
+        assert_eq!(image.get_pixel(2, 0).0[0], 255);
+    }
+
+    #[test]
+    fn equalize_stretch_range() {
+        let image = GrayImage::from_fn(16, 1, |x, _| Luma([100 + x as u8]));
+        let image = equalize(image);
+        assert_eq!(image.get_pixel(0, 0).0[0], 0);
+        assert_eq!(image.get_pixel(15, 0).0[0], 255);
+    }
+
+    #[test]
+    fn edge_mark_boundary() {
+        let image = GrayImage::from_fn(32, 32, |x, _| Luma([if x < 16 { 0 } else { 255 }]));
+        for mode in [EdgeMode::Sobel, EdgeMode::Canny] {
+            let edge = Pipeline(vec![Step::Edge(mode)]).apply_filters(image.clone());
+            assert!(edge.get_pixel(16, 16).0[0] < 128, "{mode:?}");
+            assert_eq!(edge.get_pixel(4, 16).0[0], 255, "{mode:?}");
+            assert_eq!(edge.get_pixel(28, 16).0[0], 255, "{mode:?}");
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use image::{DynamicImage, GenericImageView};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::ansi::{self, ColorMode};
+use crate::braille::{self, Style, ThresholdMode};
+use crate::dither::{self, DitherMode};
+use crate::preprocess::Pipeline;
+
+// output never exceed this many cell in either direction
+const MAX_CELLS: u32 = 2000;
+
+/// How large the output is, in characters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sizing {
+    /// `n` characters per row, keep aspect ratio
+    Width(u32),
+    /// `n` rows, keep aspect ratio
+    Height(u32),
+    /// As large as possible while fitting in `messages` messages of `chars` characters
+    /// each, counting newline and color escape. Rows are never split across messages
+    Budget { chars: usize, messages: usize },
+    /// Exactly `width` x `height` characters, ignoring aspect ratio
+    Exact { width: u32, height: u32 },
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("Invalid size `{0}`, expected `WIDTH`, `xHEIGHT` or `WIDTHxHEIGHT`")]
+pub struct ParseSizingError(String);
+
+/// Parse `W` (width), `xH` (height) or `WxH` (exact), budget has no text form
+impl FromStr for Sizing {
+    type Err = ParseSizingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseSizingError(s.to_owned());
+        let number = |n: &str| n.trim().parse::<u32>().ok().filter(|&n| n > 0);
+
+        match s.split_once(['x', 'X']) {
+            None => number(s).map(Sizing::Width),
+            Some((width, height)) if width.trim().is_empty() => number(height).map(Sizing::Height),
+            Some((width, height)) => Some(Sizing::Exact {
+                width: number(width).ok_or_else(error)?,
+                height: number(height).ok_or_else(error)?,
+            }),
+        }
+        .ok_or_else(error)
+    }
+}
+
+impl Sizing {
+    /// Dimension (in pixel) to resize image of `old_dim` to
+    ///
+    /// Color escape are unknown before rendering, the budget then only count
+    /// characters and [`render`] shrink it further.
+    pub fn image_size(self, old_dim: (u32, u32), cell_size: (u32, u32)) -> (u32, u32) {
+        let ((w, h), (cw, ch)) = (old_dim, cell_size);
+        match self {
+            Sizing::Width(cols) => braille::fit_width(old_dim, cell_size, cols.min(MAX_CELLS)),
+            Sizing::Height(rows) => {
+                let rows = rows.min(MAX_CELLS) as u64;
+                // inverse of `braille::fit_width`
+                let cols = (rows * 2 * w as u64).div_ceil(h as u64);
+                let (w2, h2) =
+                    braille::fit_width(old_dim, cell_size, cols.min(MAX_CELLS as u64) as u32);
+                (w2, h2.min(rows as u32 * ch))
+            }
+            Sizing::Budget { chars, messages } => {
+                let (mut w2, mut h2) =
+                    braille::calculate_image_size(old_dim, cell_size, chars * messages);
+                // rows can't be split, so leftover space at the end of each message is wasted
+                let fit = |(w2, h2): (u32, u32)| {
+                    let rows_per_message = chars / (w2 / cw + 1) as usize;
+                    h2.div_ceil(ch) as usize <= messages * rows_per_message
+                };
+                while w2 > 0 && !fit((w2, h2)) {
+                    (w2, h2) = braille::fit_width(old_dim, cell_size, w2 / cw - 1);
+                }
+                (w2, h2)
+            }
+            Sizing::Exact { width, height } => {
+                (cw * width.min(MAX_CELLS), ch * height.min(MAX_CELLS))
+            }
+        }
+    }
+}
+
+/// How to turn an image into text
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    pub style: Style,
+    pub invert: bool,
+    pub monospace: bool,
+    pub threshold: ThresholdMode,
+    pub dither: DitherMode,
+    pub color: ColorMode,
+    pub preprocess: Pipeline,
+    pub size: Sizing,
+}
+
+// whether `rows` fit in `messages` pages of `chars` characters (newline included)
+// without splitting a row
+fn fits(rows: &[String], chars: usize, messages: usize) -> bool {
+    let (mut pages, mut used) = (1, 0);
+    for row in rows {
+        let len = row.chars().count() + 1;
+        if len > chars {
+            return false;
+        }
+        if used + len > chars {
+            pages += 1;
+            used = 0;
+        }
+        used += len;
+    }
+    pages <= messages
+}
+
+/// Render image into rows of text
+pub fn render(image: DynamicImage, options: &RenderOptions) -> Vec<String> {
+    let cell_size = options.style.renderer().cell_size();
+    let image = options.preprocess.apply_geometry(image);
+
+    // Resize image to requested char width
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return Vec::new();
+    }
+    let (mut w2, mut h2) = options.size.image_size((w, h), cell_size);
+    loop {
+        if w2 == 0 || h2 == 0 {
+            return Vec::new();
+        }
+        let rows = render_resized(&image, (w2, h2), options);
+        let Sizing::Budget { chars, messages } = options.size else {
+            return rows;
+        };
+        if fits(&rows, chars, messages) {
+            return rows;
+        }
+        // over budget by color escape, shrink the area by as much (at least a column)
+        let cols = w2 / cell_size.0;
+        let total = rows
+            .iter()
+            .map(|row| row.chars().count() + 1)
+            .sum::<usize>();
+        let scale = ((chars * messages) as f64 / total as f64).sqrt();
+        let cols = ((cols as f64 * scale) as u32).min(cols.saturating_sub(1));
+        (w2, h2) = braille::fit_width((w, h), cell_size, cols);
+    }
+}
+
+// render `image` resized to `size` (in pixel)
+fn render_resized(image: &DynamicImage, size: (u32, u32), options: &RenderOptions) -> Vec<String> {
+    let renderer = options.style.renderer();
+    let resized = image.resize_exact(size.0, size.1, image::imageops::FilterType::CatmullRom);
+    let mut image = options.preprocess.apply_filters(resized.to_luma8());
+
+    // Convert image to text
+    let config = braille::BrailleConfig {
+        invert: options.invert,
+        monospace: options.monospace,
+        threshold_mode: options.threshold,
+        dither: options.dither,
+        ..Default::default()
+    };
+    if renderer.is_bilevel() {
+        let threshold = braille::compute_threshold(&image, &config);
+        dither::dither(&mut image, config.dither, &threshold);
+    }
+    let rows = match options.style {
+        Style::Braille => braille::gray_to_patterns(&image, &config),
+        _ => braille::image_to_text(&image, renderer, &config)
+            .map(|row| row.collect())
+            .collect(),
+    };
+    ansi::colorize(
+        &rows,
+        &resized.to_rgb8(),
+        &image,
+        renderer.cell_size(),
+        &config,
+        options.color,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden;
+
+    fn options() -> RenderOptions {
+        RenderOptions {
+            style: Style::Braille,
+            invert: false,
+            monospace: true,
+            threshold: ThresholdMode::Fixed,
+            dither: DitherMode::FloydSteinberg,
+            color: ColorMode::None,
+            preprocess: Pipeline::default(),
+            size: Sizing::Width(40),
+        }
+    }
+
+    #[test]
+    fn golden_render() {
+        let configs = [
+            ("default", options()),
+            (
+                "invert-otsu-atkinson",
+                RenderOptions {
+                    invert: true,
+                    threshold: ThresholdMode::Otsu,
+                    dither: DitherMode::Atkinson,
+                    ..options()
+                },
+            ),
+            (
+                "sextant-adaptive",
+                RenderOptions {
+                    style: Style::Sextant,
+                    threshold: ThresholdMode::Adaptive,
+                    dither: DitherMode::None,
+                    ..options()
+                },
+            ),
+            (
+                "ascii",
+                RenderOptions {
+                    style: Style::Ascii,
+                    ..options()
+                },
+            ),
+            (
+                "half-block-color",
+                RenderOptions {
+                    style: Style::HalfBlock,
+                    color: ColorMode::Full,
+                    ..options()
+                },
+            ),
+            (
+                "edge",
+                RenderOptions {
+                    preprocess: "contrast=20,edge=sobel".parse().unwrap(),
+                    dither: DitherMode::None,
+                    ..options()
+                },
+            ),
+        ];
+        for sample in golden::SAMPLES {
+            let image = image::open(sample).unwrap();
+            for (name, options) in &configs {
+                let text = render(image.clone(), options)
+                    .into_iter()
+                    .map(|row| row + "\n")
+                    .collect::<String>();
+                golden::assert_golden(
+                    &format!("render/{}-{name}.txt", golden::sample_name(sample)),
+                    &text,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sizing() {
+        let cell = (2, 4);
+        assert_eq!(Sizing::Width(30).image_size((100, 100), cell), (60, 60));
+        assert_eq!(Sizing::Height(30).image_size((100, 100), cell), (120, 120));
+        assert_eq!(
+            Sizing::Exact {
+                width: 10,
+                height: 3
+            }
+            .image_size((100, 100), cell),
+            (20, 12)
+        );
+    }
+
+    #[test]
+    fn budget_fit_in_messages() {
+        for (dim, messages) in itertools::iproduct!([(100, 100), (640, 480), (300, 900)], 1..=5) {
+            let size = Sizing::Budget {
+                chars: 1990,
+                messages,
+            };
+            let (w2, h2) = size.image_size(dim, (2, 4));
+            let (cols, rows) = (w2 / 2, h2.div_ceil(4));
+            let rows_per_message = 1990 / (cols + 1);
+            assert!(
+                rows <= messages as u32 * rows_per_message,
+                "{dim:?} {messages}"
+            );
+        }
+    }
+
+    #[test]
+    fn budget_fit_with_color() {
+        let image = image::open(golden::SAMPLES[0]).unwrap();
+        for (color, messages) in
+            itertools::iproduct!([ColorMode::Foreground, ColorMode::Full], 1..=3)
+        {
+            let rows = render(
+                image.clone(),
+                &RenderOptions {
+                    style: Style::HalfBlock,
+                    color,
+                    size: Sizing::Budget {
+                        chars: 1990,
+                        messages,
+                    },
+                    ..options()
+                },
+            );
+            assert!(!rows.is_empty());
+            // each message filled with whole rows
+            let mut pages = vec![0];
+            for row in &rows {
+                let len = row.chars().count() + 1;
+                assert!(len <= 1990);
+                if pages.last().unwrap() + len > 1990 {
+                    pages.push(0);
+                }
+                *pages.last_mut().unwrap() += len;
+            }
+            assert!(pages.len() <= messages, "{color:?} {messages}: {pages:?}");
+        }
+    }
+
+    #[test]
+    fn parse_sizing() {
+        assert_eq!("40".parse(), Ok(Sizing::Width(40)));
+        assert_eq!("x12".parse(), Ok(Sizing::Height(12)));
+        assert_eq!(
+            "40x12".parse(),
+            Ok(Sizing::Exact {
+                width: 40,
+                height: 12
+            })
+        );
+        for invalid in ["", "0", "x", "40x", "x0", "-3", "axb", "1x2x3"] {
+            assert!(invalid.parse::<Sizing>().is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,1076 @@
+//! Run code of some language in the [sandbox](crate::sandbox)
+//!
+//! A [`Language`] is an interpreter command on a script file, with optionally a
+//! prelude files prepended to the code. Python follows the sandbox
+//! [`policy`](crate::policy).
+use async_process::Stdio;
+use futures_lite::{future, AsyncRead, AsyncReadExt, AsyncWriteExt};
+use rand::Rng;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::Output;
+use std::sync::OnceLock;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::plot;
+use crate::policy;
+use crate::sandbox::{self, ResourceLimits, Sandbox};
+use crate::RNG;
+
+// visible in the sandbox beside the interpreter's own prefix
+const SYSTEM_BINDS: [&str; 6] = [
+    "/usr",
+    "/bin",
+    "/lib",
+    "/lib64",
+    "/etc/ld.so.cache",
+    // where some of `/usr/bin` link to, e.g. `lua` and `cc`
+    "/etc/alternatives",
+];
+const SYSTEM_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+const WORKDIR_SIZE: usize = 16 << 20;
+const LIMITS: ResourceLimits = ResourceLimits {
+    memory: 512 << 20,
+    cpu: 5,
+    processes: 32,
+    file_size: 8 << 20,
+};
+// bytes of stdout and stderr each
+pub const OUTPUT_LIMIT: usize = 64 << 10;
+const PYTHON_HEADER: &str = "./python_dir/header.py";
+const PLOT_PRELUDE: &str = "./python_dir/plot.py";
+const PYTHON_LOCATE: &[&str] = &[
+    "python3",
+    "-c",
+    "import sys; print(sys.prefix); print(sys.base_prefix)",
+];
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("exceeded timelimit of {timeout:?}")]
+    Timeout { timeout: Duration },
+    #[error("exceeded memory limit of {} MiB", limit >> 20)]
+    MemoryLimit { limit: u64 },
+    /// `output` is cut at `limit` with a note at the end
+    #[error("exceeded output limit of {limit} bytes")]
+    OutputLimit { limit: usize, output: Output },
+    #[error("killed by {}", signal_name(*.0))]
+    Killed(i32),
+    #[error("{language} is not available")]
+    Unavailable { language: &'static str },
+    #[error("too many code running, try again later")]
+    Busy,
+    #[error("can't read prelude file {path}")]
+    Prelude {
+        path: &'static str,
+        source: std::io::Error,
+    },
+    #[error("process io error")]
+    IO(#[from] std::io::Error),
+}
+
+/// Code to run and what it's given
+#[derive(Debug, Default)]
+pub struct Input {
+    pub code: String,
+    pub stdin: Vec<u8>,
+    /// data files (name and content), read-only in the working directory
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+impl From<String> for Input {
+    fn from(code: String) -> Self {
+        Input {
+            code,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&str> for Input {
+    fn from(code: &str) -> Self {
+        code.to_owned().into()
+    }
+}
+
+/// How to run code of a language
+#[derive(Debug)]
+pub struct Language {
+    pub name: &'static str,
+    /// code block tags, the first one is the main
+    pub tags: &'static [&'static str],
+    /// of the script file
+    pub extension: &'static str,
+    /// program and arguments, `{}` is replaced with the script file
+    pub command: &'static [&'static str],
+    /// files (on the host) prepended to the code
+    pub prelude: &'static [&'static str],
+    /// host command printing the prefixes the interpreter is installed in (one
+    /// per line), they are visible in the sandbox with their `bin` in `PATH`
+    pub locate: Option<&'static [&'static str]>,
+    pub env: &'static [(&'static str, &'static str)],
+    /// start of stderr last line when the interpreter run out of memory
+    pub memory_error: Option<&'static str>,
+    pub limits: ResourceLimits,
+    /// bytes of stdout and stderr each
+    pub output_limit: usize,
+    /// the sandbox policy replaces the interpreter and memory limit, and its
+    /// prelude goes first
+    pub policy: bool,
+    runtime: OnceLock<Option<Runtime>>,
+}
+
+// sandbox and `PATH` the interpreter run with
+#[derive(Debug)]
+struct Runtime {
+    sandbox: Sandbox,
+    path: String,
+}
+
+pub static PYTHON: Language = Language {
+    name: "Python",
+    tags: &["py", "python", "python3"],
+    extension: "py",
+    command: &["python3", "{}"],
+    prelude: &[PYTHON_HEADER],
+    locate: Some(PYTHON_LOCATE),
+    env: &[
+        ("PYTHONDONTWRITEBYTECODE", "1"),
+        // a thread per core would blow the memory limit
+        ("OPENBLAS_NUM_THREADS", "1"),
+    ],
+    memory_error: Some("MemoryError"),
+    limits: LIMITS,
+    output_limit: OUTPUT_LIMIT,
+    policy: true,
+    runtime: OnceLock::new(),
+};
+
+/// Python with matplotlib and PIL, see `python_dir/plot.py`
+pub static PYTHON_PLOT: Language = Language {
+    name: "Python",
+    tags: &[],
+    extension: "py",
+    command: &["python3", "{}"],
+    prelude: &[PLOT_PRELUDE, PYTHON_HEADER],
+    locate: Some(PYTHON_LOCATE),
+    env: &[
+        ("PYTHONDONTWRITEBYTECODE", "1"),
+        // a thread per core would blow the memory limit
+        ("OPENBLAS_NUM_THREADS", "1"),
+        ("MPLBACKEND", "Agg"),
+    ],
+    memory_error: Some("MemoryError"),
+    limits: LIMITS,
+    // images are sent on stdout too
+    output_limit: OUTPUT_LIMIT + plot::MAX_IMAGES * (plot::MAX_IMAGE_SIZE + 64),
+    policy: true,
+    runtime: OnceLock::new(),
+};
+
+pub static JAVASCRIPT: Language = Language {
+    name: "JavaScript",
+    tags: &["js", "javascript", "node"],
+    extension: "js",
+    // the heap is limited by node itself
+    command: &["node", "--max-old-space-size=256", "{}"],
+    prelude: &[],
+    locate: None,
+    env: &[],
+    memory_error: None,
+    // v8 reserve a lot of address space upfront
+    limits: ResourceLimits {
+        memory: 4 << 30,
+        ..LIMITS
+    },
+    output_limit: OUTPUT_LIMIT,
+    policy: false,
+    runtime: OnceLock::new(),
+};
+
+pub static RUST: Language = Language {
+    name: "Rust",
+    tags: &["rust", "rs"],
+    extension: "rs",
+    // compiled into the working directory, under the same limits as the program
+    command: &["sh", "-c", "rustc --edition 2021 -o main {} && exec ./main"],
+    prelude: &[],
+    locate: Some(&["rustc", "--print", "sysroot"]),
+    env: &[],
+    memory_error: None,
+    limits: LIMITS,
+    output_limit: OUTPUT_LIMIT,
+    policy: false,
+    runtime: OnceLock::new(),
+};
+
+pub static LUA: Language = Language {
+    name: "Lua",
+    tags: &["lua"],
+    extension: "lua",
+    command: &["lua", "{}"],
+    prelude: &[],
+    locate: None,
+    env: &[],
+    memory_error: Some("lua: not enough memory"),
+    limits: LIMITS,
+    output_limit: OUTPUT_LIMIT,
+    policy: false,
+    runtime: OnceLock::new(),
+};
+
+pub static SHELL: Language = Language {
+    name: "Shell",
+    tags: &["sh", "shell"],
+    extension: "sh",
+    command: &["sh", "{}"],
+    prelude: &[],
+    locate: None,
+    env: &[],
+    memory_error: None,
+    limits: LIMITS,
+    output_limit: OUTPUT_LIMIT,
+    policy: false,
+    runtime: OnceLock::new(),
+};
+
+pub static LANGUAGES: [&Language; 5] = [&PYTHON, &JAVASCRIPT, &RUST, &LUA, &SHELL];
+
+/// Language with the code block tag `tag`
+pub fn find(tag: &str) -> Option<&'static Language> {
+    let tag = tag.to_ascii_lowercase();
+    LANGUAGES
+        .into_iter()
+        .find(|language| language.tags.contains(&tag.as_str()))
+}
+
+/// A code block of a message
+#[derive(Debug, PartialEq)]
+pub struct CodeBlock<'a> {
+    /// tag right after the opening fence, e.g. `py`
+    pub tag: Option<&'a str>,
+    pub code: &'a str,
+}
+
+/// Code blocks in `text`, or `text` itself (without inline code quotes) if there is none
+pub fn code_blocks(text: &str) -> Vec<CodeBlock<'_>> {
+    let text = text.trim();
+    if !text.contains("```") {
+        let code = text
+            .strip_prefix('`')
+            .and_then(|code| code.strip_suffix('`'))
+            .unwrap_or(text);
+        return vec![CodeBlock { tag: None, code }];
+    }
+
+    text.split("```")
+        .skip(1)
+        .step_by(2)
+        .map(|block| match block.split_once('\n') {
+            Some((tag, code))
+                if !tag.is_empty()
+                    && tag
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+#-_".contains(c)) =>
+            {
+                CodeBlock {
+                    tag: Some(tag),
+                    code,
+                }
+            }
+            _ => CodeBlock {
+                tag: None,
+                code: block,
+            },
+        })
+        .collect()
+}
+
+impl Language {
+    // found once, `None` if it isn't installed
+    fn runtime(&self) -> Option<&Runtime> {
+        self.runtime.get_or_init(|| self.find_runtime()).as_ref()
+    }
+
+    fn find_runtime(&self) -> Option<Runtime> {
+        let prefixes = match self.locate {
+            Some([program, args @ ..]) => {
+                // the interpreter locates itself
+                let program = if self.policy { self.program() } else { program };
+                let output = std::process::Command::new(program)
+                    .args(args)
+                    .output()
+                    .ok()
+                    .filter(|output| output.status.success())?;
+                String::from_utf8(output.stdout)
+                    .ok()?
+                    .lines()
+                    .map(PathBuf::from)
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        let path = itertools::join(
+            prefixes
+                .iter()
+                .map(|prefix| prefix.join("bin").display().to_string())
+                .chain([SYSTEM_PATH.to_owned()]),
+            ":",
+        );
+        let program = self.program();
+        if !std::env::split_paths(&path).any(|dir| dir.join(program).is_file()) {
+            return None;
+        }
+
+        let binds = SYSTEM_BINDS.map(PathBuf::from).into_iter().chain(prefixes);
+        Some(Runtime {
+            sandbox: Sandbox {
+                binds: binds.collect(),
+                workdir_size: WORKDIR_SIZE,
+                limits: self.limits(),
+            },
+            path,
+        })
+    }
+
+    fn script_name(&self) -> String {
+        format!("main.{}", self.extension)
+    }
+
+    // first of the command
+    fn program(&self) -> &str {
+        if self.policy {
+            &policy::get().interpreter
+        } else {
+            self.command.first().copied().unwrap_or_default()
+        }
+    }
+
+    /// Limits it runs with by default
+    pub fn limits(&self) -> ResourceLimits {
+        if self.policy {
+            ResourceLimits {
+                memory: policy::get().memory_limit,
+                ..self.limits
+            }
+        } else {
+            self.limits
+        }
+    }
+
+    /// Code prepended to the script: the policy prelude then the prelude files
+    pub fn prelude(&self) -> Result<Vec<u8>, Error> {
+        let mut prelude = Vec::new();
+        if self.policy {
+            prelude.extend(policy::get().prelude().into_bytes());
+        }
+        for &path in self.prelude {
+            let file = std::fs::read(path).map_err(|source| Error::Prelude { path, source })?;
+            prelude.extend(file);
+        }
+        Ok(prelude)
+    }
+}
+
+fn signal_name(signal: i32) -> String {
+    match signal {
+        libc::SIGKILL => "SIGKILL".to_owned(),
+        libc::SIGSEGV => "SIGSEGV".to_owned(),
+        libc::SIGABRT => "SIGABRT".to_owned(),
+        libc::SIGXCPU => "SIGXCPU (cpu time limit)".to_owned(),
+        libc::SIGXFSZ => "SIGXFSZ (file size limit)".to_owned(),
+        _ => format!("signal {signal}"),
+    }
+}
+
+// read `pipe` to the end, or until `limit` then kill the sandbox spawned as `pid` as
+// the rest is unwanted
+async fn read_capped(
+    pipe: impl AsyncRead + Unpin,
+    limit: usize,
+    pid: u32,
+) -> io::Result<(Vec<u8>, bool)> {
+    let mut buf = Vec::new();
+    pipe.take(limit as u64 + 1).read_to_end(&mut buf).await?;
+    let truncated = buf.len() > limit;
+    if truncated {
+        sandbox::kill(pid)?;
+        truncate(&mut buf, limit);
+    }
+    Ok((buf, truncated))
+}
+
+/// Cut `output` to `limit` bytes, with a note saying so
+pub fn truncate(output: &mut Vec<u8>, limit: usize) {
+    output.truncate(limit);
+    output.extend_from_slice(format!("\n[truncated at {limit} bytes]").as_bytes());
+}
+
+/// Random line telling the output of sandboxed code apart from what it send after
+pub fn marker() -> String {
+    format!("{:016x}", RNG.with_borrow_mut(|rng| rng.random::<u64>()))
+}
+
+/// Run `input` as `language` code in the sandbox, and return its stdout and stderr
+pub async fn run(
+    language: &'static Language,
+    input: Input,
+    timeout: Duration,
+) -> Result<Output, Error> {
+    // prelude then user code
+    let mut script = language.prelude()?;
+    script.extend_from_slice(input.code.as_bytes());
+    let files = input
+        .files
+        .iter()
+        .map(|(name, content)| (name.as_str(), content.as_slice()))
+        .collect::<Vec<_>>();
+    language
+        .spawn(&script, &files)?
+        .wait(input.stdin, timeout)
+        .await
+}
+
+/// Sandboxed process blocked on its stdin until [`Process::wait`]
+#[derive(Debug)]
+pub struct Process {
+    language: &'static Language,
+    limits: ResourceLimits,
+    child: async_process::Child,
+}
+
+impl Language {
+    /// Start `script` (without the prelude) in the sandbox, with `files` read-only
+    /// beside it
+    pub fn spawn(&'static self, script: &[u8], files: &[(&str, &[u8])]) -> Result<Process, Error> {
+        self.spawn_with(script, files, self.limits())
+    }
+
+    /// [`Language::spawn`] with other limits than the language's
+    pub fn spawn_with(
+        &'static self,
+        script: &[u8],
+        files: &[(&str, &[u8])],
+        limits: ResourceLimits,
+    ) -> Result<Process, Error> {
+        let runtime = self.runtime().ok_or(Error::Unavailable {
+            language: self.name,
+        })?;
+        let script_name = self.script_name();
+        if files.iter().any(|(name, _)| *name == script_name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("file can't be named `{script_name}`"),
+            )
+            .into());
+        }
+        let files = files
+            .iter()
+            .copied()
+            .chain([(script_name.as_str(), script)])
+            .collect::<Vec<_>>();
+
+        // run it in the sandbox working directory
+        let args = self
+            .command
+            .iter()
+            .skip(1)
+            .map(|arg| arg.replace("{}", &script_name));
+        let mut command = std::process::Command::new(self.program());
+        command
+            .args(args)
+            .env_clear()
+            .env("PATH", &runtime.path)
+            .env("HOME", sandbox::WORKDIR)
+            .env("TMPDIR", sandbox::WORKDIR)
+            .envs(self.env.iter().copied());
+        let sandbox = Sandbox {
+            limits,
+            ..runtime.sandbox.clone()
+        };
+        sandbox.apply(&mut command, &files)?;
+
+        let child = async_process::Command::from(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        Ok(Process {
+            language: self,
+            limits,
+            child,
+        })
+    }
+
+    /// Whether `output` is of code that failed for running out of memory
+    pub fn out_of_memory(&self, output: &Output) -> bool {
+        // e.g. python raise MemoryError when allocation fail
+        let last_line = output
+            .stderr
+            .trim_ascii_end()
+            .rsplit(|&b| b == b'\n')
+            .next();
+        let memory_error = self.memory_error.zip(last_line);
+        !output.status.success()
+            && memory_error.is_some_and(|(error, line)| line.starts_with(error.as_bytes()))
+    }
+}
+
+impl Process {
+    /// Give it `stdin` and wait at most `timeout` for it to exit
+    pub async fn wait(mut self, stdin: Vec<u8>, timeout: Duration) -> Result<Output, Error> {
+        let child = &mut self.child;
+        let pid = child.id();
+        let limit = self.language.output_limit;
+        let mut pipe = child.stdin.take().expect("piped stdin");
+        let stdout = child.stdout.take().expect("piped stdout");
+        let stderr = child.stderr.take().expect("piped stderr");
+        let feed = async move {
+            // closed once written, the code may exit without reading all of it
+            match pipe.write_all(&stdin).await {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                result => result,
+            }
+        };
+        let process = async {
+            let (_, (stdout, stderr)) = future::try_zip(
+                feed,
+                future::try_zip(
+                    read_capped(stdout, limit, pid),
+                    read_capped(stderr, limit, pid),
+                ),
+            )
+            .await?;
+            io::Result::Ok((child.status().await?, stdout, stderr))
+        };
+
+        let Ok(result) = tokio::time::timeout(timeout, process).await else {
+            sandbox::kill(pid)?;
+            return Err(Error::Timeout { timeout });
+        };
+        let (status, (stdout, stdout_cut), (stderr, stderr_cut)) = result?;
+        let output = Output {
+            status,
+            stdout,
+            stderr,
+        };
+
+        if stdout_cut || stderr_cut {
+            return Err(Error::OutputLimit { limit, output });
+        }
+        if let Some(signal) = status.signal() {
+            return Err(Error::Killed(signal));
+        }
+        if self.language.out_of_memory(&output) {
+            return Err(Error::MemoryLimit {
+                limit: self.limits.memory,
+            });
+        }
+        Ok(output)
+    }
+
+    /// The sandboxed process itself, with its stdin, stdout and stderr piped
+    pub fn into_child(self) -> async_process::Child {
+        self.child
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    async fn secure_run_python_code(input: Input, timeout: Duration) -> Result<Output, Error> {
+        run(&PYTHON, input, timeout).await
+    }
+
+    #[tokio::test]
+    async fn hello_world() {
+        let output = secure_run_python_code("print(1+1)".into(), Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert!(output.stderr.is_empty());
+        assert_eq!(output.stdout, b"2\n");
+    }
+
+    #[tokio::test]
+    async fn capture_error() {
+        let output = secure_run_python_code("print(1/0)".into(), Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("ZeroDivisionError"));
+    }
+
+    #[tokio::test]
+    async fn allow_whitelist_import() {
+        let output = secure_run_python_code(
+            "import math; print(math.sqrt(4))".into(),
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap();
+        assert!(output.stderr.is_empty());
+        assert_eq!(output.stdout, b"2.0\n");
+    }
+
+    #[tokio::test]
+    async fn prevent_other_import() {
+        let output = secure_run_python_code(
+            "import os; print(os.listdir)".into(),
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap();
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("secure_importer"));
+    }
+
+    #[tokio::test]
+    async fn prevet_other_import_in_exec() {
+        let output = secure_run_python_code(
+            "exec('import os; print(os.listdir)')".into(),
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap();
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("secure_importer"));
+    }
+
+    #[tokio::test]
+    async fn prevent_importlib_workaround() {
+        let output = secure_run_python_code(
+            "os = importlib.__import__('os'); print(os.listdir())".into(),
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap();
+        assert!(output.stdout.is_empty());
+        assert!(!output.stderr.is_empty());
+    }
+
+    #[tokio::test]
+    async fn prevent_importlib_workaround_in_exec() {
+        let output = secure_run_python_code(
+            "exec('os = importlib.__import__(\"os\")\nprint(os.listdir())')".into(),
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap();
+        assert!(output.stdout.is_empty());
+        assert!(!output.stderr.is_empty());
+    }
+
+    #[tokio::test]
+    async fn prevent_loader_workaround() {
+        let output = secure_run_python_code(
+            "os = __loader__.load_module('os'); print(os.listdir())".into(),
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap();
+        assert!(output.stdout.is_empty());
+        dbg!(String::from_utf8_lossy(&output.stderr));
+        assert!(!output.stderr.is_empty());
+    }
+
+    #[tokio::test]
+    async fn prevent_vardict_workaround() {
+        let output = secure_run_python_code(
+            "os = __builtins__.__dict__['os']; print(os.listdir())".into(),
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap();
+        assert!(output.stdout.is_empty());
+        assert!(!output.stderr.is_empty());
+    }
+
+    // get the real `os` module back without `__import__`
+    pub(crate) const ESCAPE: &str = "os = next(c for c in ().__class__.__base__.__subclasses__() \
+        if c.__name__ == '_wrap_close').__init__.__globals__['sys'].modules['os']\n";
+
+    async fn escape(code: &str) -> String {
+        let output =
+            secure_run_python_code(format!("{ESCAPE}{code}").into(), Duration::from_secs(5))
+                .await
+                .unwrap();
+        assert!(
+            output.stderr.is_empty(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[tokio::test]
+    async fn escape_read_host_file() {
+        let mut secret = tempfile::NamedTempFile::new_in(".").unwrap();
+        std::io::Write::write_all(&mut secret, b"secret").unwrap();
+        let path = secret.path().canonicalize().unwrap();
+        let code = format!(
+            "try:\n    os.open({path:?}, os.O_RDONLY); print('read')\n\
+             except OSError as e: print('blocked')",
+        );
+        assert_eq!(escape(&code).await, "blocked\n");
+        // nothing of the host but what python need
+        let root = escape("print(sorted(os.listdir('/')))").await;
+        assert!(!root.contains("tmp") && !root.contains("home"), "{root}");
+    }
+
+    #[tokio::test]
+    async fn escape_write_root() {
+        let code = "for path in ['/pwned', '/usr/pwned', '/dev/pwned', '/proc/sys/kernel/hostname']:\n    \
+            try:\n        os.close(os.open(path, os.O_WRONLY | os.O_CREAT)); print('wrote', path)\n    \
+            except OSError: pass";
+        assert_eq!(escape(code).await, "");
+    }
+
+    #[tokio::test]
+    async fn escape_workdir_writable() {
+        let code = "fd = os.open('out.txt', os.O_WRONLY | os.O_CREAT); os.write(fd, b'ok'); os.close(fd)\n\
+            print(os.getcwd(), os.read(os.open('out.txt', os.O_RDONLY), 2))";
+        assert_eq!(escape(code).await, "/work b'ok'\n");
+    }
+
+    #[tokio::test]
+    async fn escape_network() {
+        // undo the import whitelist entirely
+        let code = "modules = os.sys.modules\n\
+            modules['builtins'].__import__ = modules['importlib'].__import__\n\
+            import socket\n\
+            try:\n    socket.create_connection(('1.1.1.1', 53), timeout=2); print('connected')\n\
+            except OSError: print('blocked')";
+        assert_eq!(escape(code).await, "blocked\n");
+    }
+
+    #[tokio::test]
+    async fn escape_syscalls() {
+        // denied by seccomp even where the namespaces would allow them
+        let code = "modules = os.sys.modules\n\
+            modules['builtins'].__import__ = modules['importlib'].__import__\n\
+            import ctypes\n\
+            libc = ctypes.CDLL(None, use_errno=True)\n\
+            print(libc.unshare(0x10000000), ctypes.get_errno())\n\
+            print(libc.mount(b'none', b'/work', b'tmpfs', 0, None), ctypes.get_errno())\n\
+            print(libc.ptrace(0, 0, 0, 0), ctypes.get_errno())";
+        assert_eq!(escape(code).await, "-1 1\n".repeat(3));
+    }
+
+    #[tokio::test]
+    async fn escape_processes() {
+        // only itself and init in its pid namespace
+        let code = "print(os.getpid(), [p for p in os.listdir('/proc') if p.isdigit()])";
+        assert_eq!(escape(code).await, "2 ['1', '2']\n");
+    }
+
+    #[tokio::test]
+    async fn escape_privilege() {
+        let code = "print(os.getuid() != 0, os.environ.get('DISCORD_TOKEN'))\n\
+            try:\n    os.setuid(0); print('root')\n\
+            except OSError: pass";
+        assert_eq!(escape(code).await, "True None\n");
+    }
+
+    #[tokio::test]
+    async fn memory_limit() {
+        let result =
+            secure_run_python_code("print(len('a' * 10**10))".into(), Duration::from_secs(5)).await;
+        assert!(
+            matches!(result, Err(Error::MemoryLimit { .. })),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn cpu_limit() {
+        let result =
+            secure_run_python_code("while True: pass".into(), Duration::from_secs(10)).await;
+        assert!(
+            matches!(result, Err(Error::Killed(libc::SIGXCPU))),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn output_limit() {
+        let result = secure_run_python_code(
+            "while True: print('x' * 1000)".into(),
+            Duration::from_secs(5),
+        )
+        .await;
+        let Err(Error::OutputLimit { limit, output }) = result else {
+            panic!("{result:?}");
+        };
+        assert!(output.stdout.starts_with(b"xxx"));
+        assert!(output
+            .stdout
+            .ends_with(format!("[truncated at {limit} bytes]").as_bytes()));
+        assert!(output.stdout.len() < limit + 100);
+    }
+
+    fn spawn_python(code: &str) -> Process {
+        let mut script = PYTHON.prelude().unwrap();
+        script.extend_from_slice(code.as_bytes());
+        PYTHON.spawn(&script, &[]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn timeout_kill_all() {
+        let process = spawn_python("import time\ntime.sleep(10**9)");
+        let pid = process.child.id();
+        // itself, the namespace init and python
+        assert_eq!(sandbox::tests::processes(pid).len(), 3);
+        let result = process.wait(Vec::new(), Duration::from_secs(2)).await;
+        assert!(matches!(result, Err(Error::Timeout { .. })), "{result:?}");
+        sandbox::tests::assert_all_killed(pid).await;
+    }
+
+    #[tokio::test]
+    async fn limits_kill_all() {
+        for (code, timeout) in [
+            ("while True: print(1)", Duration::from_secs(5)),
+            ("while True: pass", Duration::from_secs(1)),
+        ] {
+            let process = spawn_python(code);
+            let pid = process.child.id();
+            let result = process.wait(Vec::new(), timeout).await;
+            assert!(
+                matches!(
+                    result,
+                    Err(Error::OutputLimit { .. } | Error::Timeout { .. })
+                ),
+                "{code}: {result:?}"
+            );
+            sandbox::tests::assert_all_killed(pid).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn killed_with_spawned_process() {
+        let process = spawn_python("import time\ntime.sleep(10**9)");
+        let pid = process.child.id();
+        assert_eq!(sandbox::tests::processes(pid).len(), 3);
+        // SAFETY: not reaped yet, so the pid isn't reused
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+        sandbox::tests::assert_all_killed(pid).await;
+    }
+
+    #[tokio::test]
+    async fn killed_by_signal() {
+        let result = secure_run_python_code(
+            format!("{ESCAPE}os.kill(os.getpid(), 11)").into(),
+            Duration::from_secs(5),
+        )
+        .await;
+        assert!(
+            matches!(result, Err(Error::Killed(libc::SIGSEGV))),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn process_limit() {
+        // children block on the pipe forever
+        let code = "r, w = os.pipe()\ntry:\n    for _ in range(100):\n        if os.fork() == 0: os.read(r, 1)\n    \
+            print('forked')\nexcept OSError: print('limited')";
+        assert_eq!(escape(code).await, "limited\n");
+    }
+
+    #[tokio::test]
+    async fn file_size_limit() {
+        let code = "fd = os.open('big', os.O_WRONLY | os.O_CREAT)\n\
+            try:\n    os.write(fd, b'x' * (9 << 20)); os.write(fd, b'x'); print('written')\n\
+            except OSError: print('limited')";
+        assert_eq!(escape(code).await, "limited\n");
+    }
+
+    #[tokio::test]
+    async fn read_stdin() {
+        let input = Input {
+            code: "print(input()[::-1]); print(input())".to_owned(),
+            stdin: b"hello\nworld\n".to_vec(),
+            ..Default::default()
+        };
+        let output = secure_run_python_code(input, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"olleh\nworld\n");
+    }
+
+    #[tokio::test]
+    async fn unread_stdin() {
+        let input = Input {
+            code: "print(1)".to_owned(),
+            stdin: vec![b'x'; 1 << 20],
+            ..Default::default()
+        };
+        let output = secure_run_python_code(input, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"1\n");
+    }
+
+    #[tokio::test]
+    async fn read_only_files() {
+        let code = format!(
+            "{ESCAPE}print(os.read(os.open('data.csv', os.O_RDONLY), 100))\n\
+             for flags in [os.O_WRONLY, os.O_RDWR | os.O_TRUNC]:\n    \
+             try:\n        os.open('data.csv', flags); print('writable')\n    \
+             except OSError: pass\n\
+             try:\n    os.unlink('data.csv'); print('removed')\n\
+             except OSError: pass"
+        );
+        let input = Input {
+            code,
+            files: vec![("data.csv".to_owned(), b"a,b\n1,2\n".to_vec())],
+            ..Default::default()
+        };
+        let output = secure_run_python_code(input, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"b'a,b\\n1,2\\n'\n");
+    }
+
+    #[tokio::test]
+    async fn invalid_file_name() {
+        for name in ["main.py", "../x", ".hidden", ""] {
+            let input = Input {
+                code: "print(1)".to_owned(),
+                files: vec![(name.to_owned(), Vec::new())],
+                ..Default::default()
+            };
+            let result = secure_run_python_code(input, Duration::from_secs(2)).await;
+            assert!(
+                matches!(&result, Err(Error::IO(e)) if e.kind() == io::ErrorKind::InvalidInput),
+                "{name}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_code_blocks() {
+        assert_eq!(
+            code_blocks("```py\nprint(1)\n``` then ```\nstdin```"),
+            [
+                CodeBlock {
+                    tag: Some("py"),
+                    code: "print(1)\n"
+                },
+                CodeBlock {
+                    tag: None,
+                    code: "\nstdin"
+                },
+            ]
+        );
+        assert_eq!(
+            code_blocks("```print(1)```"),
+            [CodeBlock {
+                tag: None,
+                code: "print(1)"
+            }]
+        );
+        // not a tag but code
+        assert_eq!(code_blocks("```x = 1\ny```")[0].tag, None);
+        assert_eq!(code_blocks(" `print(1)` ")[0].code, "print(1)");
+        assert_eq!(code_blocks("print(1)")[0].code, "print(1)");
+    }
+
+    #[test]
+    fn find_language() {
+        assert_eq!(find("py").map(|l| l.name), Some("Python"));
+        assert_eq!(find("JS").map(|l| l.name), Some("JavaScript"));
+        assert_eq!(find("rs").map(|l| l.name), Some("Rust"));
+        assert_eq!(find("lua").map(|l| l.name), Some("Lua"));
+        assert!(find("cobol").is_none());
+        assert!(find("").is_none());
+    }
+
+    #[tokio::test]
+    async fn run_shell() {
+        let input = Input {
+            code: "read name; echo \"hi $name\"; cat data.txt".to_owned(),
+            stdin: b"bot\n".to_vec(),
+            files: vec![("data.txt".to_owned(), b"data".to_vec())],
+        };
+        let output = run(&SHELL, input, Duration::from_secs(2)).await.unwrap();
+        assert_eq!(output.stdout, b"hi bot\ndata");
+    }
+
+    // interpreter of the host, if installed
+    async fn run_if_available(language: &'static Language, code: &str) -> Option<Output> {
+        match run(language, code.into(), Duration::from_secs(10)).await {
+            Err(Error::Unavailable { .. }) => None,
+            result => Some(result.unwrap()),
+        }
+    }
+
+    #[tokio::test]
+    async fn run_other_languages() {
+        for (language, code) in [
+            (&JAVASCRIPT, "console.log(1 + 1)"),
+            (&RUST, "fn main() { println!(\"{}\", 1 + 1) }"),
+            (&LUA, "print(1 + 1)"),
+        ] {
+            if let Some(output) = run_if_available(language, code).await {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                assert_eq!(output.stdout, b"2\n", "{}: {stderr}", language.name);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn unavailable_language() {
+        static COBOL: Language = Language {
+            name: "COBOL",
+            tags: &["cobol"],
+            extension: "cob",
+            command: &["no-such-cobol", "{}"],
+            prelude: &[],
+            locate: None,
+            env: &[],
+            memory_error: None,
+            limits: LIMITS,
+            output_limit: OUTPUT_LIMIT,
+            policy: false,
+            runtime: OnceLock::new(),
+        };
+        let result = run(&COBOL, "".into(), Duration::from_secs(2)).await;
+        assert!(
+            matches!(result, Err(Error::Unavailable { language: "COBOL" })),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_prelude() {
+        static BROKEN: Language = Language {
+            name: "Shell",
+            tags: &[],
+            extension: "sh",
+            command: &["sh", "{}"],
+            prelude: &["./python_dir/missing.sh"],
+            locate: None,
+            env: &[],
+            memory_error: None,
+            limits: LIMITS,
+            output_limit: OUTPUT_LIMIT,
+            policy: false,
+            runtime: OnceLock::new(),
+        };
+        let result = run(&BROKEN, "".into(), Duration::from_secs(2)).await;
+        assert!(
+            matches!(
+                result,
+                Err(Error::Prelude {
+                    path: "./python_dir/missing.sh",
+                    ..
+                })
+            ),
+            "{result:?}"
+        );
+    }
+}
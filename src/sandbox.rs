@@ -0,0 +1,653 @@
+//! Run untrusted process isolated with linux namespaces
+//!
+//! The process get its own mount, pid, network, ipc and uts namespace, and a user
+//! namespace unless the bot runs as root (it then drop to `nobody` instead).
+//! Its root is an empty read-only tmpfs with only the given paths bound (read-only)
+//! at the same place, a private `/proc`, a few `/dev` nodes, and a writable tmpfs
+//! working directory at [`WORKDIR`] holding the given files (read-only).
+//!
+//! It run as pid 2 under a minimal init, with [`ResourceLimits`] applied and a
+//! seccomp filter denying syscalls that reach into the kernel's mount, namespace,
+//! tracing, bpf and keyring code. The init die with the process spawned by
+//! [`Command`], taking the whole pid namespace with it, and [`kill`] kill them all
+//! at once.
+//!
+//! The Docker image runs the bot as an unprivileged user, so it creates a user
+//! namespace. Docker's own seccomp profile and AppArmor still refuse that, so its
+//! container must be run with `--cap-add SYS_ADMIN --security-opt apparmor=unconfined`.
+//! That loosen the container around the bot itself, give it a container (or VM)
+//! of its own.
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Writable working directory of the sandboxed process
+pub const WORKDIR: &str = "/work";
+// new root is built here (hiding the host's) before pivoting into it
+const STAGING: &str = "/tmp";
+const DEVICES: [&str; 4] = ["/dev/null", "/dev/zero", "/dev/random", "/dev/urandom"];
+// id the process see itself as in its user namespace
+const SANDBOX_ID: u32 = 1000;
+const NOBODY: u32 = 65534;
+// denied with `EPERM` by the seccomp filter, they reach kernel code the sandboxed
+// process has no use for
+const DENIED_SYSCALLS: [libc::c_long; 22] = [
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_mount_setattr,
+    libc::SYS_fsopen,
+    libc::SYS_fsconfig,
+    libc::SYS_fsmount,
+    libc::SYS_fspick,
+    libc::SYS_move_mount,
+    libc::SYS_open_tree,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_bpf,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_userfaultfd,
+    libc::SYS_perf_event_open,
+];
+// `clone` flags making new namespaces, `clone3` pass them in memory the filter
+// can't read so it's refused with `ENOSYS` and libc falls back to `clone`
+const CLONE_NAMESPACES: libc::c_int = libc::CLONE_NEWNS
+    | libc::CLONE_NEWUSER
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWCGROUP;
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e; // AUDIT_ARCH_X86_64
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7; // AUDIT_ARCH_AARCH64
+                                     // x32 syscalls on x86_64 have this bit set, bypassing a filter on the numbers
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+// `seccomp_data` offsets: `nr`, `arch` and low half of `args[0]`
+const NR: u32 = 0;
+const ARCH: u32 = 4;
+#[cfg(target_endian = "little")]
+const ARG0: u32 = 16;
+#[cfg(target_endian = "big")]
+const ARG0: u32 = 20;
+// head checking the architecture and `clone`, a jump per denied syscall, then
+// `ALLOW` and `EPERM`
+const FILTER_LEN: usize = 13 + DENIED_SYSCALLS.len() + 2;
+static SECCOMP_FILTER: [libc::sock_filter; FILTER_LEN] = seccomp_filter();
+
+/// What is visible in the sandbox
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    /// host paths mounted read-only, path that doesn't exist is skipped
+    pub binds: Vec<PathBuf>,
+    /// size limit of the working directory in bytes
+    pub workdir_size: usize,
+    pub limits: ResourceLimits,
+}
+
+/// Per process limits (`setrlimit`) of the sandboxed process
+#[derive(Clone, Copy, Debug)]
+pub struct ResourceLimits {
+    /// address space in bytes
+    pub memory: u64,
+    /// cpu time in seconds, then it get `SIGXCPU` (and `SIGKILL` a second later)
+    pub cpu: u64,
+    /// processes and threads
+    pub processes: u64,
+    /// largest file it can write in bytes
+    pub file_size: u64,
+}
+
+// one step of building the new root, paths are under `STAGING`
+enum Step {
+    Mkdir(CString),
+    Touch(CString),
+    Symlink {
+        target: CString,
+        link: CString,
+    },
+    Bind {
+        src: CString,
+        dst: CString,
+        dev: bool,
+    },
+    Write {
+        path: CString,
+        content: Vec<u8>,
+    },
+}
+
+// everything the child need, prepared beforehand as it shouldn't allocate after fork
+struct Setup {
+    as_root: bool,
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+    staging: CString,
+    old_root: CString,
+    proc: CString,
+    workdir: CString,
+    workdir_options: CString,
+    steps: Vec<Step>,
+    limits: ResourceLimits,
+}
+
+fn cstring(path: impl AsRef<Path>) -> io::Result<CString> {
+    CString::new(path.as_ref().as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+// `path` moved under `STAGING`
+fn staged(path: &Path) -> PathBuf {
+    Path::new(STAGING).join(path.strip_prefix("/").unwrap_or(path))
+}
+
+const fn statement(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+const fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: (libc::BPF_JMP | code | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+// seccomp program denying `DENIED_SYSCALLS` and new namespaces, killing the process
+// on syscall of another architecture
+const fn seccomp_filter() -> [libc::sock_filter; FILTER_LEN] {
+    let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+    let ret = libc::BPF_RET | libc::BPF_K;
+    let eperm = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+    let mut filter = [statement(ret, libc::SECCOMP_RET_ALLOW); FILTER_LEN];
+    let head = [
+        statement(load, ARCH),
+        jump(libc::BPF_JEQ, AUDIT_ARCH, 1, 0),
+        statement(ret, libc::SECCOMP_RET_KILL_PROCESS),
+        statement(load, NR),
+        jump(libc::BPF_JGE, X32_SYSCALL_BIT, 0, 1),
+        statement(ret, libc::SECCOMP_RET_KILL_PROCESS),
+        jump(libc::BPF_JEQ, libc::SYS_clone3 as u32, 0, 1),
+        statement(ret, libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32),
+        jump(libc::BPF_JEQ, libc::SYS_clone as u32, 0, 4),
+        statement(load, ARG0),
+        jump(libc::BPF_JSET, CLONE_NAMESPACES as u32, 0, 1),
+        statement(ret, eperm),
+        statement(ret, libc::SECCOMP_RET_ALLOW),
+    ];
+    let mut i = 0;
+    while i < head.len() {
+        filter[i] = head[i];
+        i += 1;
+    }
+    // jump to the last instruction (`EPERM`) on match, past the `ALLOW` before it
+    let denied = DENIED_SYSCALLS.len();
+    let mut j = 0;
+    while j < denied {
+        let nr = DENIED_SYSCALLS[j] as u32;
+        filter[i + j] = jump(libc::BPF_JEQ, nr, (denied - j) as u8, 0);
+        j += 1;
+    }
+    filter[FILTER_LEN - 1] = statement(ret, eperm);
+    filter
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl Sandbox {
+    /// Make `command` run in the sandbox with `files` (name and content) read-only
+    /// in its working directory
+    ///
+    /// `command` must not set a working directory, it start in [`WORKDIR`].
+    pub fn apply(&self, command: &mut Command, files: &[(&str, &[u8])]) -> io::Result<()> {
+        let setup = self.setup(files)?;
+        // its own group, to kill the namespace init along with it
+        command.process_group(0);
+        // SAFETY: the closure only make syscalls on data prepared before fork
+        unsafe {
+            command.pre_exec(move || setup.enter());
+        }
+        Ok(())
+    }
+
+    fn setup(&self, files: &[(&str, &[u8])]) -> io::Result<Setup> {
+        let mut steps = Vec::new();
+        let mut dirs = HashSet::new();
+        let mut mkdir_all = |steps: &mut Vec<Step>, path: &Path| -> io::Result<()> {
+            for dir in path
+                .ancestors()
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev()
+                .skip(1)
+            {
+                if dirs.insert(dir.to_owned()) {
+                    steps.push(Step::Mkdir(cstring(staged(dir))?));
+                }
+            }
+            Ok(())
+        };
+
+        let mut binds = self
+            .binds
+            .iter()
+            .filter(|path| path.is_absolute() && path.symlink_metadata().is_ok())
+            .collect::<Vec<_>>();
+        binds.sort();
+        binds.dedup_by(|path, parent| path.starts_with(parent));
+        for path in binds {
+            mkdir_all(&mut steps, path.parent().unwrap_or(path))?;
+            let dst = cstring(staged(path))?;
+            if path.is_symlink() {
+                let target = cstring(path.read_link()?)?;
+                steps.push(Step::Symlink { target, link: dst });
+                continue;
+            }
+            steps.push(if path.is_dir() {
+                Step::Mkdir(dst.clone())
+            } else {
+                Step::Touch(dst.clone())
+            });
+            steps.push(Step::Bind {
+                src: cstring(path)?,
+                dst,
+                dev: false,
+            });
+        }
+
+        mkdir_all(&mut steps, Path::new("/proc"))?;
+        mkdir_all(&mut steps, Path::new("/dev"))?;
+        for device in DEVICES.map(Path::new).into_iter().filter(|d| d.exists()) {
+            let dst = cstring(staged(device))?;
+            steps.push(Step::Touch(dst.clone()));
+            steps.push(Step::Bind {
+                src: cstring(device)?,
+                dst,
+                dev: true,
+            });
+        }
+        mkdir_all(&mut steps, Path::new(WORKDIR))?;
+        mkdir_all(&mut steps, Path::new("/.old"))?;
+
+        let mut writes = Vec::new();
+        for (i, (name, content)) in files.iter().enumerate() {
+            let duplicate = files[..i].iter().any(|(other, _)| other == name);
+            if name.is_empty() || name.contains('/') || name.starts_with('.') || duplicate {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid file name `{name}`"),
+                ));
+            }
+            writes.push(Step::Write {
+                path: cstring(staged(&Path::new(WORKDIR).join(name)))?,
+                content: content.to_vec(),
+            });
+        }
+
+        // SAFETY: always successful
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        Ok(Setup {
+            as_root: uid == 0,
+            uid_map: format!("{SANDBOX_ID} {uid} 1").into_bytes(),
+            gid_map: format!("{SANDBOX_ID} {gid} 1").into_bytes(),
+            staging: cstring(STAGING)?,
+            old_root: cstring(staged(Path::new("/.old")))?,
+            proc: cstring(staged(Path::new("/proc")))?,
+            workdir: cstring(staged(Path::new(WORKDIR)))?,
+            workdir_options: cstring(format!("size={},mode=1777", self.workdir_size))?,
+            steps: steps.into_iter().chain(writes).collect(),
+            limits: self.limits,
+        })
+    }
+}
+
+impl Setup {
+    // run in the child between fork and exec
+    fn enter(&self) -> io::Result<()> {
+        let mut flags = libc::CLONE_NEWNS
+            | libc::CLONE_NEWPID
+            | libc::CLONE_NEWNET
+            | libc::CLONE_NEWIPC
+            | libc::CLONE_NEWUTS;
+        if !self.as_root {
+            flags |= libc::CLONE_NEWUSER;
+        }
+        unsafe {
+            check(libc::unshare(flags))?;
+            if !self.as_root {
+                write_file(c"/proc/self/setgroups", b"deny")?;
+                write_file(c"/proc/self/uid_map", &self.uid_map)?;
+                write_file(c"/proc/self/gid_map", &self.gid_map)?;
+            }
+
+            // only children join the new pid namespace
+            let mut status_pipe = [0; 2];
+            check(libc::pipe2(status_pipe.as_mut_ptr(), libc::O_CLOEXEC))?;
+            let [status_read, status_write] = status_pipe;
+            match libc::fork() {
+                -1 => return Err(io::Error::last_os_error()),
+                0 => {}
+                init => wait_and_exit(init, status_read),
+            }
+            libc::close(status_read);
+
+            self.build_root()?;
+            if self.as_root {
+                check(libc::setgroups(0, std::ptr::null()))?;
+                check(libc::setresgid(NOBODY, NOBODY, NOBODY))?;
+                check(libc::setresuid(NOBODY, NOBODY, NOBODY))?;
+            }
+            // cleared by changing credentials, so only now
+            check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+            // its parent is outside the namespace (`getppid` is 0), but the only
+            // reader of the status pipe: the pipe is broken if it already died
+            let mut poll = libc::pollfd {
+                fd: status_write,
+                events: libc::POLLOUT,
+                revents: 0,
+            };
+            check(libc::poll(&mut poll, 1, 0))?;
+            if poll.revents & libc::POLLERR != 0 {
+                libc::_exit(1);
+            }
+            check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            let filter = libc::sock_fprog {
+                len: FILTER_LEN as u16,
+                filter: SECCOMP_FILTER.as_ptr().cast_mut(),
+            };
+            check(libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &filter,
+            ))?;
+
+            // pid 1 ignore signals it has no handler for, even from the kernel
+            // (e.g. `SIGXCPU`), so it only wait while the actual process is its child
+            match libc::fork() {
+                -1 => return Err(io::Error::last_os_error()),
+                0 => {}
+                child => init(child, status_write),
+            }
+            libc::close(status_write);
+            self.limits.apply()?;
+        }
+        Ok(())
+    }
+
+    unsafe fn build_root(&self) -> io::Result<()> {
+        let null = std::ptr::null::<libc::c_char>();
+        let tmpfs = c"tmpfs".as_ptr();
+        let nosuid_nodev = libc::MS_NOSUID | libc::MS_NODEV;
+
+        // don't propagate anything back to the host
+        check(libc::mount(
+            null,
+            c"/".as_ptr(),
+            null,
+            libc::MS_REC | libc::MS_PRIVATE,
+            null.cast(),
+        ))?;
+        check(libc::mount(
+            tmpfs,
+            self.staging.as_ptr(),
+            tmpfs,
+            nosuid_nodev,
+            c"mode=0755".as_ptr().cast(),
+        ))?;
+
+        for step in &self.steps {
+            match step {
+                Step::Mkdir(path) => check(libc::mkdir(path.as_ptr(), 0o755))?,
+                Step::Touch(path) => {
+                    let fd = libc::open(
+                        path.as_ptr(),
+                        libc::O_CREAT | libc::O_WRONLY | libc::O_CLOEXEC,
+                        0o644,
+                    );
+                    check(fd)?;
+                    libc::close(fd);
+                }
+                Step::Symlink { target, link } => {
+                    check(libc::symlink(target.as_ptr(), link.as_ptr()))?
+                }
+                Step::Bind { src, dst, dev } => bind_read_only(src, dst, *dev)?,
+                Step::Write { .. } => {}
+            }
+        }
+
+        // proc of the new pid namespace, while the host's one is still visible
+        let proc = c"proc".as_ptr();
+        let flags = nosuid_nodev | libc::MS_NOEXEC | libc::MS_RDONLY;
+        check(libc::mount(
+            proc,
+            self.proc.as_ptr(),
+            proc,
+            flags,
+            null.cast(),
+        ))?;
+        check(libc::mount(
+            tmpfs,
+            self.workdir.as_ptr(),
+            tmpfs,
+            nosuid_nodev,
+            self.workdir_options.as_ptr().cast(),
+        ))?;
+        for step in &self.steps {
+            if let Step::Write { path, content } = step {
+                write_file(path, content)?;
+                bind_read_only(path, path, false)?;
+            }
+        }
+
+        check(libc::syscall(
+            libc::SYS_pivot_root,
+            self.staging.as_ptr(),
+            self.old_root.as_ptr(),
+        ) as libc::c_int)?;
+        check(libc::chdir(c"/".as_ptr()))?;
+        check(libc::umount2(c"/.old".as_ptr(), libc::MNT_DETACH))?;
+        check(libc::rmdir(c"/.old".as_ptr()))?;
+        let flags = libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY | nosuid_nodev;
+        check(libc::mount(null, c"/".as_ptr(), null, flags, null.cast()))?;
+
+        let workdir = &self.workdir.as_bytes_with_nul()[STAGING.len()..];
+        check(libc::chdir(workdir.as_ptr().cast()))?;
+        Ok(())
+    }
+}
+
+unsafe fn bind_read_only(src: &CString, dst: &CString, dev: bool) -> io::Result<()> {
+    let null = std::ptr::null::<libc::c_char>();
+    check(libc::mount(
+        src.as_ptr(),
+        dst.as_ptr(),
+        null,
+        libc::MS_BIND,
+        null.cast(),
+    ))?;
+    let mut flags = libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY | libc::MS_NOSUID;
+    if !dev {
+        flags |= libc::MS_NODEV;
+    }
+    // flags of mount from the host are locked in a user namespace, keep them
+    flags |= locked_flags(dst)?;
+    check(libc::mount(null, dst.as_ptr(), null, flags, null.cast()))
+}
+
+// mount flags of `path` that can't be cleared by remount in a user namespace
+unsafe fn locked_flags(path: &CString) -> io::Result<libc::c_ulong> {
+    let mut stat = std::mem::zeroed::<libc::statvfs>();
+    check(libc::statvfs(path.as_ptr(), &mut stat))?;
+    let flags = [
+        (libc::ST_NOSUID, libc::MS_NOSUID),
+        (libc::ST_NODEV, libc::MS_NODEV),
+        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+        (libc::ST_NOATIME, libc::MS_NOATIME),
+        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+        (libc::ST_RELATIME, libc::MS_RELATIME),
+    ];
+    Ok(flags
+        .into_iter()
+        .filter(|(st, _)| stat.f_flag & st != 0)
+        .fold(0, |acc, (_, ms)| acc | ms))
+}
+
+unsafe fn write_file(path: &std::ffi::CStr, mut content: &[u8]) -> io::Result<()> {
+    let fd = libc::open(
+        path.as_ptr(),
+        libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC,
+        0o644,
+    );
+    check(fd)?;
+    while !content.is_empty() {
+        let written = libc::write(fd, content.as_ptr().cast(), content.len());
+        if written == -1 {
+            let error = io::Error::last_os_error();
+            libc::close(fd);
+            return Err(error);
+        }
+        content = &content[written as usize..];
+    }
+    check(libc::close(fd))
+}
+
+/// Kill the sandboxed process spawned as `pid` and everything in its pid namespace
+///
+/// It must not be reaped yet, or the pid may be reused.
+pub fn kill(pid: u32) -> io::Result<()> {
+    // SAFETY: no memory involved
+    match check(unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) }) {
+        // all already exited
+        Err(e) if e.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+        result => result,
+    }
+}
+
+impl ResourceLimits {
+    unsafe fn apply(&self) -> io::Result<()> {
+        let limits = [
+            (libc::RLIMIT_AS, self.memory, self.memory),
+            (libc::RLIMIT_CPU, self.cpu, self.cpu + 1),
+            (libc::RLIMIT_NPROC, self.processes, self.processes),
+            (libc::RLIMIT_FSIZE, self.file_size, self.file_size),
+        ];
+        for (resource, soft, hard) in limits {
+            let limit = libc::rlimit {
+                rlim_cur: soft,
+                rlim_max: hard,
+            };
+            check(libc::setrlimit(resource, &limit))?;
+        }
+        Ok(())
+    }
+}
+
+// close every file descriptor but `keep`, which is moved to 3
+//
+// process that never exec must not keep the exec error pipe of `Command` open,
+// or the spawn would block until it exit.
+unsafe fn close_fds(keep: libc::c_int) {
+    libc::dup2(keep, 3);
+    libc::syscall(libc::SYS_close_range, 4, libc::c_uint::MAX, 0);
+}
+
+// wait for `pid`, retrying on interrupt
+unsafe fn wait(pid: libc::pid_t) -> Option<(libc::pid_t, libc::c_int)> {
+    let mut status = 0;
+    loop {
+        match libc::waitpid(pid, &mut status, 0) {
+            -1 if io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) => {}
+            -1 => return None,
+            pid => return Some((pid, status)),
+        }
+    }
+}
+
+// init of the pid namespace, reap everything and send `child`'s wait status to `status_fd`
+unsafe fn init(child: libc::pid_t, status_fd: libc::c_int) -> ! {
+    close_fds(status_fd);
+    while let Some((pid, status)) = wait(-1) {
+        if pid == child {
+            libc::write(3, (&raw const status).cast(), size_of_val(&status));
+            libc::_exit(0);
+        }
+    }
+    libc::_exit(1)
+}
+
+// wait for the namespace init and exit the same way as the sandboxed process
+unsafe fn wait_and_exit(init: libc::pid_t, status_fd: libc::c_int) -> ! {
+    close_fds(status_fd);
+    let Some((_, mut status)) = wait(init) else {
+        libc::_exit(1);
+    };
+    // init exit before sending only when the sandbox couldn't be set up
+    let mut child_status = 0;
+    let size = size_of_val(&child_status);
+    if libc::read(3, (&raw mut child_status).cast(), size) == size as isize {
+        status = child_status;
+    }
+
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::time::Duration;
+
+    /// Running processes of the sandbox spawned as `pid`, i.e. of its group
+    pub fn processes(pid: u32) -> Vec<u32> {
+        std::fs::read_dir("/proc")
+            .unwrap()
+            .filter_map(|entry| {
+                let stat = std::fs::read_to_string(entry.ok()?.path().join("stat")).ok()?;
+                // state, parent and group follow the command name
+                let (id, rest) = stat.split_once(" (")?;
+                let fields = rest.rsplit_once(") ")?.1.split(' ').collect::<Vec<_>>();
+                let running = !matches!(fields[0], "Z" | "X");
+                (running && fields[2] == pid.to_string()).then(|| id.parse().unwrap())
+            })
+            .collect()
+    }
+
+    /// Wait a bit for the sandbox spawned as `pid` to be gone, as it's killed
+    /// asynchronously
+    pub async fn assert_all_killed(pid: u32) {
+        for _ in 0..50 {
+            if processes(pid).is_empty() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("still running: {:?}", processes(pid));
+    }
+}
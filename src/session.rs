@@ -0,0 +1,567 @@
+//! Python sessions keeping their variables between snippets, at most one per user
+use async_process::{Child, ChildStderr, ChildStdin, ChildStdout};
+use futures_lite::{future, AsyncRead, AsyncReadExt, AsyncWriteExt};
+use poise::serenity_prelude::UserId;
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Output};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::runner::{self, Input, OUTPUT_LIMIT, PYTHON};
+use crate::sandbox::{self, ResourceLimits};
+
+const SESSION: &str = "./python_dir/session.py";
+// cpu time is of the whole session
+const LIMITS: ResourceLimits = ResourceLimits {
+    memory: 256 << 20,
+    cpu: 60,
+    processes: 32,
+    file_size: 8 << 20,
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("You already have a session")]
+    Exists,
+    #[error("You don't have a session")]
+    NotFound,
+    #[error("Too many sessions, try again later")]
+    Full,
+    #[error(transparent)]
+    Run(#[from] runner::Error),
+}
+
+/// Python process running snippets in the same namespace
+#[derive(Debug)]
+pub struct Session {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    stderr: ChildStderr,
+    // end of a snippet's output, before its status
+    marker: Vec<u8>,
+    // data files given at start, for reset
+    files: Vec<(String, Vec<u8>)>,
+    last_used: Instant,
+    ended: bool,
+}
+
+// how far `read_output` got
+enum Read {
+    // with the snippet's status
+    Done(i32),
+    Ended,
+    Overflow,
+}
+
+// read `pipe` until `marker` and the status after it, or kill the sandbox spawned as
+// `pid` when it print more than `OUTPUT_LIMIT`
+async fn read_output(
+    pipe: &mut (impl AsyncRead + Unpin),
+    marker: &[u8],
+    pid: u32,
+) -> io::Result<(Vec<u8>, Read)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        let n = pipe.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok((buf, Read::Ended));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        // marker, a digit and a line break
+        let end = buf.len().saturating_sub(marker.len() + 2);
+        if buf.len() >= marker.len() + 2 && buf[end..].starts_with(marker) && buf.ends_with(b"\n") {
+            // the code can print the marker too, with anything after it
+            let status = buf[buf.len() - 2];
+            if !status.is_ascii_digit() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "session printed an invalid status",
+                ));
+            }
+            buf.truncate(end);
+            return Ok((buf, Read::Done((status - b'0') as i32)));
+        }
+        if buf.len() > OUTPUT_LIMIT + marker.len() + 2 {
+            sandbox::kill(pid)?;
+            runner::truncate(&mut buf, OUTPUT_LIMIT);
+            return Ok((buf, Read::Overflow));
+        }
+    }
+}
+
+impl Session {
+    /// Start a session with `files` read-only in its working directory
+    pub async fn start(files: Vec<(String, Vec<u8>)>) -> Result<Self, runner::Error> {
+        let header = PYTHON.prelude()?;
+        let script = std::fs::read(SESSION)?;
+        let all_files = files
+            .iter()
+            .map(|(name, content)| (name.as_str(), content.as_slice()))
+            .chain([("header.py", header.as_slice())])
+            .collect::<Vec<_>>();
+        let mut child = PYTHON.spawn_with(&script, &all_files, LIMITS)?.into_child();
+
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        let stdout = child.stdout.take().expect("piped stdout");
+        let stderr = child.stderr.take().expect("piped stderr");
+        let marker = runner::marker();
+        stdin.write_all(format!("{marker}\n").as_bytes()).await?;
+        Ok(Session {
+            child,
+            stdin,
+            stdout,
+            stderr,
+            marker: format!("\n{marker} ").into_bytes(),
+            files,
+            last_used: Instant::now(),
+            ended: false,
+        })
+    }
+
+    /// Run `input` code (without data files), the session end if it exit, time
+    /// out or print too much
+    pub async fn run(&mut self, input: Input, timeout: Duration) -> Result<Output, runner::Error> {
+        if !input.files.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "data files can only be given when the session start",
+            )
+            .into());
+        }
+        self.last_used = Instant::now();
+
+        let mut request = format!("{} {}\n", input.code.len(), input.stdin.len()).into_bytes();
+        request.extend_from_slice(input.code.as_bytes());
+        request.extend_from_slice(&input.stdin);
+        let pid = self.child.id();
+        let process = async {
+            self.stdin.write_all(&request).await?;
+            self.stdin.flush().await?;
+            future::try_zip(
+                read_output(&mut self.stdout, &self.marker, pid),
+                read_output(&mut self.stderr, &self.marker, pid),
+            )
+            .await
+        };
+        let ((stdout, stdout_read), (stderr, stderr_read)) =
+            match tokio::time::timeout(timeout, process).await {
+                Ok(Ok(output)) => output,
+                Ok(Err(e)) => {
+                    self.end().await?;
+                    return Err(e.into());
+                }
+                Err(_) => {
+                    self.end().await?;
+                    return Err(runner::Error::Timeout { timeout });
+                }
+            };
+
+        let status = match (stdout_read, stderr_read) {
+            (Read::Done(status), Read::Done(_)) => ExitStatus::from_raw(status << 8),
+            (Read::Overflow, _) | (_, Read::Overflow) => {
+                let output = Output {
+                    status: self.end().await?,
+                    stdout,
+                    stderr,
+                };
+                return Err(runner::Error::OutputLimit {
+                    limit: OUTPUT_LIMIT,
+                    output,
+                });
+            }
+            _ => {
+                // e.g. `exit()`
+                self.ended = true;
+                let status = self.child.status().await?;
+                if let Some(signal) = status.signal() {
+                    return Err(runner::Error::Killed(signal));
+                }
+                status
+            }
+        };
+        let output = Output {
+            status,
+            stdout,
+            stderr,
+        };
+        if PYTHON.out_of_memory(&output) {
+            return Err(runner::Error::MemoryLimit {
+                limit: LIMITS.memory,
+            });
+        }
+        Ok(output)
+    }
+
+    // kill it with everything in its sandbox, and wait for it to exit
+    async fn end(&mut self) -> io::Result<ExitStatus> {
+        // once reaped, its pid may be reused
+        if !self.ended {
+            self.ended = true;
+            sandbox::kill(self.child.id())?;
+        }
+        self.child.status().await
+    }
+}
+
+type Shared = Arc<tokio::sync::Mutex<Session>>;
+
+/// Sessions of users, ended when idle for too long
+pub struct Sessions {
+    sessions: Arc<Mutex<HashMap<UserId, Shared>>>,
+    max: usize,
+}
+
+impl Sessions {
+    /// At most `max` sessions, each ending after `idle` without running code
+    pub fn new(idle: Duration, max: usize) -> Self {
+        let sessions = Arc::new(Mutex::new(HashMap::<UserId, Shared>::new()));
+        let weak = Arc::downgrade(&sessions);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(idle / 2);
+            loop {
+                interval.tick().await;
+                let Some(sessions) = weak.upgrade() else {
+                    break;
+                };
+                let mut expired = Vec::new();
+                sessions.lock().expect("not poisoned").retain(|_, session| {
+                    // one that is running code is in use
+                    let used = session
+                        .try_lock()
+                        .map_or(true, |session| session.last_used.elapsed() < idle);
+                    if !used {
+                        expired.push(session.clone());
+                    }
+                    used
+                });
+                for session in expired {
+                    if let Err(e) = session.lock().await.end().await {
+                        eprintln!("Failed to end idle session: {e}");
+                    }
+                }
+            }
+        });
+        Sessions { sessions, max }
+    }
+
+    /// Start a session for `user`
+    pub async fn start(&self, user: UserId, files: Vec<(String, Vec<u8>)>) -> Result<(), Error> {
+        {
+            let sessions = self.sessions.lock().expect("not poisoned");
+            if sessions.contains_key(&user) {
+                return Err(Error::Exists);
+            }
+            if sessions.len() >= self.max {
+                return Err(Error::Full);
+            }
+        }
+        let mut session = Session::start(files).await?;
+        let error = {
+            let mut sessions = self.sessions.lock().expect("not poisoned");
+            // started meanwhile, or others took the last places
+            if sessions.contains_key(&user) {
+                Error::Exists
+            } else if sessions.len() >= self.max {
+                Error::Full
+            } else {
+                sessions.insert(user, Arc::new(tokio::sync::Mutex::new(session)));
+                return Ok(());
+            }
+        };
+        if let Err(e) = session.end().await {
+            eprintln!("Failed to end extra session: {e}");
+        }
+        Err(error)
+    }
+
+    /// End the session of `user`
+    pub async fn stop(&self, user: UserId) -> Result<(), Error> {
+        let session = self
+            .sessions
+            .lock()
+            .expect("not poisoned")
+            .remove(&user)
+            .ok_or(Error::NotFound)?;
+        session
+            .lock()
+            .await
+            .end()
+            .await
+            .map_err(runner::Error::from)?;
+        Ok(())
+    }
+
+    /// Start over the session of `user`, with the same data files
+    pub async fn reset(&self, user: UserId) -> Result<(), Error> {
+        let session = self
+            .sessions
+            .lock()
+            .expect("not poisoned")
+            .get(&user)
+            .cloned()
+            .ok_or(Error::NotFound)?;
+        let mut session = session.lock().await;
+        session.end().await.map_err(runner::Error::from)?;
+        let files = std::mem::take(&mut session.files);
+        *session = Session::start(files).await?;
+        Ok(())
+    }
+
+    /// Run `input` in the session of `user`, or give it back if there is none
+    pub async fn run(
+        &self,
+        user: UserId,
+        input: Input,
+        timeout: Duration,
+    ) -> Result<Result<Output, runner::Error>, Input> {
+        let session = self
+            .sessions
+            .lock()
+            .expect("not poisoned")
+            .get(&user)
+            .cloned();
+        let Some(session) = session else {
+            return Err(input);
+        };
+        let mut running = session.lock().await;
+        let result = running.run(input, timeout).await;
+        if running.ended {
+            let mut sessions = self.sessions.lock().expect("not poisoned");
+            // unless it was stopped and started again meanwhile
+            if sessions
+                .get(&user)
+                .is_some_and(|current| Arc::ptr_eq(current, &session))
+            {
+                sessions.remove(&user);
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::tests::ESCAPE;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn run(sessions: &Sessions, user: UserId, code: &str) -> Output {
+        sessions
+            .run(user, code.into(), TIMEOUT)
+            .await
+            .expect("has session")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn keep_variables() {
+        let sessions = Sessions::new(Duration::from_secs(60), 2);
+        let user = UserId::new(1);
+        sessions.start(user, Vec::new()).await.unwrap();
+
+        let output = run(&sessions, user, "x = 20\nprint('set')").await;
+        assert_eq!(output.stdout, b"set\n");
+        let output = run(&sessions, user, "x += 1\nx * 2").await;
+        assert_eq!(output.stdout, b"42\n");
+        assert!(output.status.success());
+    }
+
+    #[tokio::test]
+    async fn stdin_and_errors() {
+        let sessions = Sessions::new(Duration::from_secs(60), 2);
+        let user = UserId::new(1);
+        sessions.start(user, Vec::new()).await.unwrap();
+
+        let input = Input {
+            code: "name = input()".to_owned(),
+            stdin: b"bot\n".to_vec(),
+            ..Default::default()
+        };
+        sessions.run(user, input, TIMEOUT).await.unwrap().unwrap();
+        let output = run(&sessions, user, "print(name)\n1/0").await;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.stdout, b"bot\n");
+        assert!(!output.status.success());
+        assert!(stderr.contains("File \"<in 2>\", line 2"), "{stderr}");
+        assert!(stderr
+            .trim_end()
+            .ends_with("ZeroDivisionError: division by zero"));
+
+        // header still apply
+        let output = run(&sessions, user, "import os").await;
+        assert!(String::from_utf8_lossy(&output.stderr).contains("secure_importer"));
+        let output = run(&sessions, user, "print(name").await;
+        assert!(String::from_utf8_lossy(&output.stderr).contains("SyntaxError"));
+        assert_eq!(run(&sessions, user, "name").await.stdout, b"'bot'\n");
+    }
+
+    #[tokio::test]
+    async fn one_per_user() {
+        let sessions = Sessions::new(Duration::from_secs(60), 2);
+        let (alice, bob, carol) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        sessions.start(alice, Vec::new()).await.unwrap();
+        assert!(matches!(
+            sessions.start(alice, Vec::new()).await,
+            Err(Error::Exists)
+        ));
+        sessions.start(bob, Vec::new()).await.unwrap();
+        assert!(matches!(
+            sessions.start(carol, Vec::new()).await,
+            Err(Error::Full)
+        ));
+
+        // separate namespaces
+        run(&sessions, alice, "x = 1").await;
+        let output = run(&sessions, bob, "print('x' in globals())").await;
+        assert_eq!(output.stdout, b"False\n");
+
+        sessions.stop(alice).await.unwrap();
+        assert!(matches!(sessions.stop(alice).await, Err(Error::NotFound)));
+        assert!(sessions.run(alice, "".into(), TIMEOUT).await.is_err());
+        sessions.start(carol, Vec::new()).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_start() {
+        let sessions = Arc::new(Sessions::new(Duration::from_secs(60), 2));
+        let starts = (1..=4).map(|id| {
+            let sessions = sessions.clone();
+            tokio::spawn(async move { sessions.start(UserId::new(id), Vec::new()).await })
+        });
+        let mut started = 0;
+        for start in starts.collect::<Vec<_>>() {
+            match start.await.unwrap() {
+                Ok(()) => started += 1,
+                Err(Error::Full) => {}
+                Err(e) => panic!("{e}"),
+            }
+        }
+        assert_eq!(started, 2);
+        assert_eq!(sessions.sessions.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn reset() {
+        let sessions = Sessions::new(Duration::from_secs(60), 1);
+        let user = UserId::new(1);
+        assert!(matches!(sessions.reset(user).await, Err(Error::NotFound)));
+        let files = vec![("data.txt".to_owned(), b"data".to_vec())];
+        sessions.start(user, files).await.unwrap();
+        run(&sessions, user, "x = 1").await;
+
+        sessions.reset(user).await.unwrap();
+        // `open` is removed by the header, get `os` back
+        let code = format!(
+            "{ESCAPE}print('x' in globals(), os.read(os.open('data.txt', os.O_RDONLY), 10))"
+        );
+        let output = run(&sessions, user, &code).await;
+        assert_eq!(output.stdout, b"False b'data'\n");
+    }
+
+    #[tokio::test]
+    async fn idle_expiry() {
+        let sessions = Sessions::new(Duration::from_millis(200), 1);
+        let user = UserId::new(1);
+        sessions.start(user, Vec::new()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(sessions.run(user, "".into(), TIMEOUT).await.is_err());
+        sessions.start(user, Vec::new()).await.unwrap();
+    }
+
+    async fn session_pid(sessions: &Sessions, user: UserId) -> u32 {
+        let session = sessions.sessions.lock().unwrap()[&user].clone();
+        let pid = session.lock().await.child.id();
+        pid
+    }
+
+    #[tokio::test]
+    async fn nothing_left_running() {
+        let sessions = Sessions::new(Duration::from_millis(200), 1);
+        let user = UserId::new(1);
+        sessions.start(user, Vec::new()).await.unwrap();
+        let pid = session_pid(&sessions, user).await;
+        sessions.reset(user).await.unwrap();
+        sandbox::tests::assert_all_killed(pid).await;
+
+        let pid = session_pid(&sessions, user).await;
+        sessions.stop(user).await.unwrap();
+        sandbox::tests::assert_all_killed(pid).await;
+
+        sessions.start(user, Vec::new()).await.unwrap();
+        let pid = session_pid(&sessions, user).await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        sandbox::tests::assert_all_killed(pid).await;
+    }
+
+    #[tokio::test]
+    async fn ended() {
+        let sessions = Sessions::new(Duration::from_secs(60), 1);
+        let user = UserId::new(1);
+        for code in [
+            "exit()",
+            "while True: pass",
+            "while True: print('x' * 1000)",
+        ] {
+            sessions.start(user, Vec::new()).await.unwrap();
+            let pid = session_pid(&sessions, user).await;
+            let result = sessions
+                .run(user, code.into(), Duration::from_secs(2))
+                .await
+                .unwrap();
+            assert!(
+                matches!(
+                    result,
+                    Ok(_) | Err(runner::Error::Timeout { .. } | runner::Error::OutputLimit { .. })
+                ),
+                "{code}: {result:?}"
+            );
+            // gone, so a new one can start
+            assert!(
+                sessions.run(user, "".into(), TIMEOUT).await.is_err(),
+                "{code}"
+            );
+            sandbox::tests::assert_all_killed(pid).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn spoofed_status() {
+        let sessions = Sessions::new(Duration::from_secs(60), 1);
+        let user = UserId::new(1);
+        sessions.start(user, Vec::new()).await.unwrap();
+        let pid = session_pid(&sessions, user).await;
+        // alone in a read, as the real one would be
+        let code = format!(
+            "{ESCAPE}print(f\"\\n{{os.sys.modules['__main__'].marker}} /\", flush=True)\n\
+             import time; time.sleep(1)"
+        );
+        let result = sessions.run(user, code.into(), TIMEOUT).await.unwrap();
+        assert!(matches!(result, Err(runner::Error::IO(_))), "{result:?}");
+        assert!(sessions.run(user, "".into(), TIMEOUT).await.is_err());
+        sandbox::tests::assert_all_killed(pid).await;
+    }
+
+    #[tokio::test]
+    async fn memory_limit() {
+        let sessions = Sessions::new(Duration::from_secs(60), 1);
+        let user = UserId::new(1);
+        sessions.start(user, Vec::new()).await.unwrap();
+        let result = sessions
+            .run(user, "x = 'a' * (300 << 20)".into(), TIMEOUT)
+            .await
+            .unwrap();
+        assert!(
+            matches!(result, Err(runner::Error::MemoryLimit { .. })),
+            "{result:?}"
+        );
+        // still usable
+        assert_eq!(run(&sessions, user, "1 + 1").await.stdout, b"2\n");
+    }
+}
@@ -1,78 +1,376 @@
 use color_eyre::eyre::Result;
-use image::GenericImageView;
-use poise::{command, serenity_prelude::Attachment};
+use image::ImageFormat;
+use poise::serenity_prelude::{Attachment, CreateAttachment};
+use poise::{command, CreateReply};
+use std::io::Cursor;
+use std::time::Duration;
 
-use crate::{braille, Context, DISCORD_MESSAGE_LIMIT, DISCORD_WIDTH_LIMIT};
+use disbot_v2::ansi::ColorMode;
+use disbot_v2::braille::{self, Style, ThresholdMode};
+use disbot_v2::dither::DitherMode;
+use disbot_v2::preprocess::Pipeline;
+use disbot_v2::render::{self, RenderOptions, Sizing};
+use disbot_v2::{animation, decode};
 
-const N_CHAR_IN_ROW: usize = DISCORD_WIDTH_LIMIT;
-const ROW_PER_MESSAGE: usize = DISCORD_MESSAGE_LIMIT / N_CHAR_IN_ROW;
+use crate::fetch::{self, Source};
+use crate::paginator::Paginator;
+use crate::{Context, DISCORD_MESSAGE_LIMIT, DISCORD_WIDTH_LIMIT};
+
+const DEFAULT_WIDTH: u32 = DISCORD_WIDTH_LIMIT as u32 - 1; // -1 from newline
+const MAX_WIDTH: u32 = 500;
+const MAX_HEIGHT: u32 = 500;
+const DEFAULT_MAX_MESSAGES: u8 = 5;
+const MAX_MESSAGES: u8 = 10;
+const CODE_BLOCK_OVERHEAD: usize = "```\n```".len();
+const MAX_BRAILLE_FILE_SIZE: u32 = 1 << 20;
+const DEFAULT_SCALE: u8 = 4;
+const MAX_SCALE: u8 = 16;
+// discord rate limit message edit, so don't play faster than this
+const MIN_FRAME_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_PLAYBACK_DURATION: Duration = Duration::from_secs(30);
+
+struct UnicodeOptions {
+    render: RenderOptions,
+    /// send as attachment if output need more message than this
+    max_messages: u8,
+}
 
 /// Convert a provided image into text (braille unicode)
+///
+/// The image is either attached, given by `source` (an image url, custom emoji
+/// or user mention for their avatar) or in the message being replied to.
+///
+/// Animated gif, png and webp are played back by editing a single message.
+///
+/// `size` is `W` characters wide, `xH` rows tall, exactly `WxH`,
+/// or `fit` to be as large as `max_messages` messages allow.
+///
+/// `style` other than braille is sent in a code block so it stays aligned,
+/// `color` use an ansi code block with discord's 8 colors.
+///
+/// `preprocess` is a comma separated list of steps, applied in order:
+/// `crop=X:Y:W:H`, `rotate=90|180|270` and `flip[=h|v]` on the original image
+/// first, then `brightness=N`, `contrast=N`, `gamma=N`, `equalize`,
+/// `sharpen[=SIGMA]` and `edge[=sobel|canny]` on the resized one,
+/// e.g. `crop=0:0:400:300,contrast=30,edge=canny`
 #[command(prefix_command, slash_command)]
+#[allow(clippy::too_many_arguments)]
 pub async fn unicode(
     ctx: Context<'_>,
-    image: Attachment,
+    image: Option<Attachment>,
     invert: bool,
     monospace: bool,
+    style: Option<Style>,
+    threshold: Option<ThresholdMode>,
+    dither: Option<DitherMode>,
+    color: Option<ColorMode>,
+    size: Option<String>,
+    max_messages: Option<u8>,
+    preprocess: Option<String>,
+    source: Option<String>,
 ) -> Result<()> {
-    unicode_inner(ctx, image, invert, monospace).await
+    let preprocess = match preprocess
+        .as_deref()
+        .unwrap_or_default()
+        .parse::<Pipeline>()
+    {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            ctx.reply(e.to_string()).await?;
+            return Ok(());
+        }
+    };
+
+    let mut render = RenderOptions {
+        style: style.unwrap_or_default(),
+        invert,
+        monospace,
+        threshold: threshold.unwrap_or_default(),
+        dither: dither.unwrap_or_default(),
+        color: color.unwrap_or_default(),
+        preprocess,
+        size: Sizing::Width(DEFAULT_WIDTH),
+    };
+    let max_messages = max_messages
+        .unwrap_or(DEFAULT_MAX_MESSAGES)
+        .clamp(1, MAX_MESSAGES);
+    render.size = match size.as_deref().map(str::trim) {
+        None => Sizing::Width(DEFAULT_WIDTH),
+        Some("fit") => Sizing::Budget {
+            chars: page_limit(code_block(&render)),
+            messages: max_messages as usize,
+        },
+        Some(size) => match size.parse() {
+            Ok(Sizing::Width(width)) => Sizing::Width(width.min(MAX_WIDTH)),
+            Ok(Sizing::Height(height)) => Sizing::Height(height.min(MAX_HEIGHT)),
+            Ok(Sizing::Exact { width, height }) => Sizing::Exact {
+                width: width.min(MAX_WIDTH),
+                height: height.min(MAX_HEIGHT),
+            },
+            Ok(size) => size,
+            Err(e) => {
+                ctx.reply(e.to_string()).await?;
+                return Ok(());
+            }
+        },
+    };
+    let options = UnicodeOptions {
+        render,
+        max_messages,
+    };
+    let url = match (image, source) {
+        (Some(image), _) => image.url,
+        (None, Some(source)) => match fetch::parse_source(&source) {
+            Some(Source::Url(url)) => url,
+            Some(Source::User(user)) => user.to_user(ctx).await?.face(),
+            None => {
+                ctx.reply("Source must be an image url, custom emoji or user mention")
+                    .await?;
+                return Ok(());
+            }
+        },
+        (None, None) => match replied_image(ctx) {
+            Some(url) => url,
+            None => {
+                ctx.reply("Must have an image attachment, a source or reply to an image")
+                    .await?;
+                return Ok(());
+            }
+        },
+    };
+    unicode_inner(ctx, &url, options).await
+}
+
+/// Image in the message the command is replying to
+fn replied_image(ctx: Context<'_>) -> Option<String> {
+    match ctx {
+        poise::Context::Prefix(ctx) => fetch::message_image(ctx.msg.referenced_message.as_deref()?),
+        poise::Context::Application(_) => None,
+    }
+}
+
+async fn unicode_inner(ctx: Context<'_>, url: &str, options: UnicodeOptions) -> Result<()> {
+    let limits = ctx.data().image_limits;
+    let image_data = match fetch::download(url, limits.max_bytes).await {
+        Ok(data) => data,
+        Err(e) => {
+            ctx.reply(e.to_string()).await?;
+            return Ok(());
+        }
+    };
+
+    let render_options = options.render.clone();
+    let deadline = std::time::Instant::now() + limits.timeout;
+    let task = tokio::task::spawn_blocking(move || {
+        let render = |image| render::render(image, &render_options);
+        if animation::is_animated(&image_data) {
+            animation::decode(
+                &image_data,
+                MIN_FRAME_INTERVAL,
+                MAX_PLAYBACK_DURATION,
+                &limits,
+                deadline,
+                render,
+            )
+        } else {
+            decode::decode(&image_data, &limits).map(|image| vec![(Duration::ZERO, render(image))])
+        }
+    });
+    // animation stop decoding at the deadline, a still image can't be interrupted
+    // so the thread keep going after timeout, but it's bounded by the limits
+    let frames = match tokio::time::timeout(limits.timeout, task).await {
+        Ok(frames) => frames?,
+        Err(_) => Err(decode::Error::Timeout {
+            timeout: limits.timeout,
+        }),
+    };
+    match frames {
+        Ok(frames) => play(ctx, frames, &options).await,
+        Err(e) => {
+            ctx.reply(e.to_string()).await?;
+            Ok(())
+        }
+    }
+}
+
+/// Language of the code block to wrap each message in
+fn code_block(options: &RenderOptions) -> Option<&'static str> {
+    if options.color != ColorMode::None {
+        Some("ansi")
+    } else if options.style != Style::Braille {
+        Some("")
+    } else {
+        None
+    }
+}
+
+/// Characters left for the text in a message
+fn page_limit(code_block: Option<&str>) -> usize {
+    DISCORD_MESSAGE_LIMIT - code_block.map_or(0, |lang| CODE_BLOCK_OVERHEAD + lang.len())
+}
+
+fn wrap(page: String, code_block: Option<&str>) -> String {
+    match code_block {
+        Some(lang) => format!("```{lang}\n{page}```"),
+        None => page,
+    }
+}
+
+async fn send_rows(ctx: Context<'_>, rows: Vec<String>, options: &UnicodeOptions) -> Result<()> {
+    let code_block = code_block(&options.render);
+    let page_limit = page_limit(code_block);
+    // row split across messages would break the image (and color escape)
+    let row_fit = rows.iter().all(|row| row.chars().count() < page_limit);
+    let pages = Paginator::new(rows.into_iter(), page_limit).collect::<Vec<_>>();
+
+    // Produce messages
+    if pages.is_empty() {
+        ctx.reply("Nothing left to render").await?;
+        return Ok(());
+    }
+    if !row_fit || pages.len() > options.max_messages as usize {
+        // too many messages, send as attachment
+        let reply = CreateReply::default().attachment(CreateAttachment::bytes(
+            pages.concat().into_bytes(),
+            "unicode.txt",
+        ));
+        ctx.send(reply).await?;
+        return Ok(());
+    }
+    for page in pages {
+        ctx.say(wrap(page, code_block)).await?;
+    }
+
+    Ok(())
 }
 
-async fn unicode_inner(
+/// Play an animation back by editing one message frame by frame
+///
+/// Single frame is sent like a still image.
+async fn play(
     ctx: Context<'_>,
-    image: Attachment,
-    invert: bool,
-    monospace: bool,
+    frames: Vec<(Duration, Vec<String>)>,
+    options: &UnicodeOptions,
 ) -> Result<()> {
-    if image.dimensions().is_none() {
-        ctx.reply("Must have an image attachment").await?;
+    let mut frames = frames.into_iter();
+    let Some((_, first)) = frames.next() else {
+        ctx.reply("Animation has no frame").await?;
+        return Ok(());
+    };
+    if frames.len() == 0 {
+        // nothing to play
+        return send_rows(ctx, first, options).await;
+    }
+    if first.is_empty() {
+        ctx.reply("Nothing left to render").await?;
         return Ok(());
     }
-    let image_data = image.download().await?;
-    let image = image::load_from_memory(&image_data)?;
 
-    // Resize image to fit into discord message limit
-    // TODO: allow resizing by char width & using multiple message to bypass character limit
-    let (w, h) = image.dimensions();
-    let w2 = 2 * (N_CHAR_IN_ROW as u32 - 1); // one braille = 2 px width, -1 from newline
-    let h2 = (h * w2) / w; // maintain aspect ratio
+    let code_block = code_block(&options.render);
+    let first = wrap(
+        first.iter().map(|row| format!("{row}\n")).collect(),
+        code_block,
+    );
+    let frames = frames
+        .map(|(at, rows)| {
+            let page = rows.iter().map(|row| format!("{row}\n")).collect();
+            (at, wrap(page, code_block))
+        })
+        .collect::<Vec<_>>();
+    if std::iter::once(&first)
+        .chain(frames.iter().map(|(_, frame)| frame))
+        .any(|frame| frame.chars().count() > DISCORD_MESSAGE_LIMIT)
+    {
+        ctx.reply("Animation frame doesn't fit in a message, try a smaller `size`")
+            .await?;
+        return Ok(());
+    }
 
-    let mut image = image
-        .resize(w2, h2, image::imageops::FilterType::CatmullRom)
-        .to_luma8();
+    let message = ctx.reply(first).await?;
+    let start = tokio::time::Instant::now();
+    for (at, frame) in frames {
+        tokio::time::sleep_until(start + at).await;
+        message
+            .edit(ctx, CreateReply::default().content(frame))
+            .await?;
+    }
+
+    Ok(())
+}
 
-    image::imageops::dither(&mut image, &image::imageops::BiLevel);
+/// Convert braille text (pasted or as .txt attachment) back into an image
+#[command(prefix_command, slash_command)]
+pub async fn from_braille(
+    ctx: Context<'_>,
+    file: Option<Attachment>,
+    invert: Option<bool>,
+    monospace: Option<bool>,
+    scale: Option<u8>,
+    #[rest] text: Option<String>,
+) -> Result<()> {
+    let text = match (file, text) {
+        (Some(file), _) => {
+            if file.size > MAX_BRAILLE_FILE_SIZE {
+                ctx.reply("Text file too large").await?;
+                return Ok(());
+            }
+            match String::from_utf8(file.download().await?) {
+                Ok(text) => text,
+                Err(_) => {
+                    ctx.reply("Attachment must be a UTF-8 text file").await?;
+                    return Ok(());
+                }
+            }
+        }
+        (None, Some(text)) => text,
+        (None, None) => {
+            ctx.reply("Must have braille text or a text attachment")
+                .await?;
+            return Ok(());
+        }
+    };
+    let text = text.trim().trim_matches('`');
 
-    // Convert image to braille patterns
     let config = braille::BrailleConfig {
-        invert,
-        monospace,
+        invert: invert.unwrap_or(false),
+        monospace: monospace.unwrap_or(false),
         ..Default::default()
     };
-    let mut pattern_iter = braille::image_to_patterns(&image, &config);
-
-    // Produce messages
-    loop {
-        let mut buf = String::with_capacity(DISCORD_MESSAGE_LIMIT);
-        pattern_iter.by_ref().take(ROW_PER_MESSAGE).for_each(|row| {
-            buf.extend(row);
-            buf.push('\n');
-        });
-        if buf.is_empty() {
-            break;
-        }
-
-        if buf.chars().count() > DISCORD_MESSAGE_LIMIT {
-            eprintln!(
-                "[WARN] Message too long: {} > {}",
-                buf.chars().count(),
-                DISCORD_MESSAGE_LIMIT
-            );
-            eprintln!("{buf}");
-        }
-        ctx.say(buf).await?;
+    let (width, height) = braille::patterns_size(text);
+    if width == 0 || height == 0 {
+        ctx.reply("Nothing to convert").await?;
+        return Ok(());
     }
 
+    let scale = scale.unwrap_or(DEFAULT_SCALE).clamp(1, MAX_SCALE) as u32;
+    let max_dimension = ctx.data().image_limits.max_dimension;
+    let scaled = |n: usize| {
+        u32::try_from(n)
+            .ok()
+            .and_then(|n| n.checked_mul(scale))
+            .filter(|&n| n <= max_dimension)
+    };
+    let (Some(width), Some(height)) = (scaled(width), scaled(height)) else {
+        ctx.reply(format!(
+            "Image would be too large (max {max_dimension}x{max_dimension} pixels), \
+             try a smaller `scale` or less text"
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    let text = text.to_owned();
+    let png = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+        let image = braille::patterns_to_image(&text, &config);
+        let image =
+            image::imageops::resize(&image, width, height, image::imageops::FilterType::Nearest);
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+        Ok(png)
+    })
+    .await??;
+    ctx.send(CreateReply::default().attachment(CreateAttachment::bytes(png, "braille.png")))
+        .await?;
     Ok(())
 }
 